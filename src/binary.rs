@@ -0,0 +1,433 @@
+use graph::{Graph, GraphNodeId};
+use graph_data::{EdgeData, NodeData};
+use osmpbfreader::{NodeId, WayId};
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use util::{invalid_data, read_array, read_f64, read_i32, read_i64, read_u32, read_u8, write_u32};
+
+// Binary Road Graph File, all values little-endian:
+//
+//   magic                "ROADGRPH"
+//   version              u32
+//   n, m, #strings       u32 each
+//   strings              #strings x (u32 byte length, UTF-8 bytes)
+//   node lat, lon        n x i32 each, decimicro degrees
+//   node osm ids         n x i64
+//   offsets              (n + 1) x u32, out edges of node i are [offsets[i], offsets[i + 1])
+//   targets              m x u32
+//   edge ids             m x u32, graph edge id of every CSR slot
+//   osm way ids          m x i64
+//   osm way pieces       m x u32
+//   length               m x f64, meters
+//   max_speed            m x u8
+//   bidirectional        m x u8
+//   name, street_type    m x u32 each, index into the string table
+//   geometry offsets     (m + 1) x u32
+//   geometry             #points x (i32 lat, i32 lon), decimicro degrees
+//
+// Edges are stored once, grouped by their source node; bidirectional edges
+// are not repeated at their target.
+pub const MAGIC: &[u8; 8] = b"ROADGRPH";
+pub const VERSION: u32 = 2;
+
+pub fn write<E: AsRef<EdgeData>>(
+    g: &Graph<NodeData, E>,
+    filename: String,
+) -> Result<(), io::Error> {
+    let mut f = io::BufWriter::new(File::create(filename)?);
+    write_graph(g, &mut f)?;
+    f.flush()
+}
+
+pub fn write_graph<E: AsRef<EdgeData>, W: Write>(
+    g: &Graph<NodeData, E>,
+    f: &mut W,
+) -> Result<(), io::Error> {
+    // CSR order: edges sorted by source, ties by id
+    let mut order: Vec<usize> = (0..g.edges.len()).collect();
+    order.sort_by_key(|&i| (g.edges[i].s.0, i));
+
+    let mut strings = Vec::new();
+    let mut string_ids = HashMap::new();
+    let mut names = Vec::with_capacity(order.len());
+    let mut street_types = Vec::with_capacity(order.len());
+    for &i in &order {
+        let data = g.edges[i].data.as_ref();
+        names.push(intern(&data.name, &mut strings, &mut string_ids));
+        street_types.push(intern(&data.street_type, &mut strings, &mut string_ids));
+    }
+
+    f.write_all(MAGIC)?;
+    write_u32(f, VERSION)?;
+    write_u32(f, g.nodes.len() as u32)?;
+    write_u32(f, g.edges.len() as u32)?;
+    write_u32(f, strings.len() as u32)?;
+
+    for s in &strings {
+        write_u32(f, s.len() as u32)?;
+        f.write_all(s.as_bytes())?;
+    }
+
+    for node in &g.nodes {
+        f.write_all(&to_decimicro(node.data.lat).to_le_bytes())?;
+    }
+    for node in &g.nodes {
+        f.write_all(&to_decimicro(node.data.lon).to_le_bytes())?;
+    }
+    for node in &g.nodes {
+        f.write_all(&node.data.osm_id.0.to_le_bytes())?;
+    }
+
+    let mut offset = 0;
+    write_u32(f, 0)?;
+    for node in &g.nodes {
+        while offset < order.len() && g.edges[order[offset]].s == node.id {
+            offset += 1;
+        }
+        write_u32(f, offset as u32)?;
+    }
+
+    for &i in &order {
+        write_u32(f, g.edges[i].t.0)?;
+    }
+    for &i in &order {
+        write_u32(f, i as u32)?;
+    }
+    for &i in &order {
+        f.write_all(&g.edges[i].data.as_ref().osm_way_id.0.to_le_bytes())?;
+    }
+    for &i in &order {
+        write_u32(f, g.edges[i].data.as_ref().osm_way_piece)?;
+    }
+    for &i in &order {
+        f.write_all(&g.edges[i].data.as_ref().length.to_le_bytes())?;
+    }
+    for &i in &order {
+        f.write_all(&[g.edges[i].data.as_ref().max_speed])?;
+    }
+    for &i in &order {
+        f.write_all(&[g.edges[i].data.as_ref().bidirectional as u8])?;
+    }
+    for name in &names {
+        write_u32(f, *name)?;
+    }
+    for street_type in &street_types {
+        write_u32(f, *street_type)?;
+    }
+
+    let mut offset = 0;
+    write_u32(f, 0)?;
+    for &i in &order {
+        offset += g.edges[i].data.as_ref().geometry.len();
+        write_u32(f, offset as u32)?;
+    }
+    for &i in &order {
+        for (lat, lon) in &g.edges[i].data.as_ref().geometry {
+            f.write_all(&to_decimicro(*lat).to_le_bytes())?;
+            f.write_all(&to_decimicro(*lon).to_le_bytes())?;
+        }
+    }
+
+    Ok(())
+}
+
+// whether the file starts with the magic header of the binary format
+pub fn is_binary_file(filename: &str) -> Result<bool, io::Error> {
+    let mut magic = [0u8; 8];
+    let mut f = File::open(filename)?;
+    let mut read = 0;
+    while read < magic.len() {
+        match f.read(&mut magic[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    Ok(&magic == MAGIC)
+}
+
+pub fn read(filename: &str) -> Result<Graph<NodeData, EdgeData>, io::Error> {
+    let mut f = io::BufReader::new(File::open(filename)?);
+    read_graph(&mut f)
+}
+
+pub fn read_graph<R: Read>(f: &mut R) -> Result<Graph<NodeData, EdgeData>, io::Error> {
+    let mut magic = [0u8; 8];
+    f.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("not a binary road graph file".to_string()));
+    }
+    let version = read_u32(f)?;
+    if version != VERSION {
+        return Err(invalid_data(format!(
+            "unsupported binary road graph version: {}",
+            version
+        )));
+    }
+
+    let n = read_u32(f)? as usize;
+    let m = read_u32(f)? as usize;
+    let string_count = read_u32(f)? as usize;
+
+    // counts and lengths are not trusted for allocations before the data
+    // behind them has been read, a corrupt header must not abort the process
+    let mut strings = Vec::new();
+    for _ in 0..string_count {
+        let len = read_u32(f)? as u64;
+        let mut bytes = Vec::new();
+        if f.by_ref().take(len).read_to_end(&mut bytes)? as u64 != len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "unexpected end of file",
+            ));
+        }
+        let s = String::from_utf8(bytes).map_err(|e| invalid_data(e.to_string()))?;
+        strings.push(s);
+    }
+
+    let lats = read_array(f, n, read_i32)?;
+    let lons = read_array(f, n, read_i32)?;
+    let osm_ids = read_array(f, n, read_i64)?;
+    let offsets = read_array(f, n + 1, read_u32)?;
+    let targets = read_array(f, m, read_u32)?;
+    let edge_ids = read_array(f, m, read_u32)?;
+    let osm_way_ids = read_array(f, m, read_i64)?;
+    let osm_way_pieces = read_array(f, m, read_u32)?;
+    let lengths = read_array(f, m, read_f64)?;
+    let max_speeds = read_array(f, m, read_u8)?;
+    let bidirectional = read_array(f, m, read_u8)?;
+    let names = read_array(f, m, read_u32)?;
+    let street_types = read_array(f, m, read_u32)?;
+    let geometry_offsets = read_array(f, m + 1, read_u32)?;
+    let point_count = geometry_offsets[m] as usize;
+    let geometry = read_array(f, 2 * point_count, read_i32)?;
+
+    let mut g = Graph {
+        nodes: Vec::with_capacity(n),
+        edges: Vec::with_capacity(m),
+    };
+    for i in 0..n {
+        g.add_node(NodeData {
+            osm_id: NodeId(osm_ids[i]),
+            lat: from_decimicro(lats[i]),
+            lon: from_decimicro(lons[i]),
+        });
+    }
+
+    if offsets[0] != 0 || offsets[n] as usize != m || geometry_offsets[0] != 0 {
+        return Err(invalid_data("invalid offsets".to_string()));
+    }
+
+    // restore the edges in the order of their ids
+    let mut sources = vec![0u32; m];
+    for s in 0..n {
+        let (start, end) = (offsets[s] as usize, offsets[s + 1] as usize);
+        if start > end || end > m {
+            return Err(invalid_data(format!("invalid edge offsets at node {}", s)));
+        }
+        for source in &mut sources[start..end] {
+            *source = s as u32;
+        }
+    }
+    let mut slots = vec![None; m];
+    for (slot, id) in edge_ids.iter().enumerate() {
+        match slots.get_mut(*id as usize) {
+            Some(x @ None) => *x = Some(slot),
+            _ => return Err(invalid_data(format!("invalid edge id: {}", id))),
+        }
+    }
+
+    let string = |i: u32| -> Result<String, io::Error> {
+        strings
+            .get(i as usize)
+            .cloned()
+            .ok_or_else(|| invalid_data(format!("invalid string index: {}", i)))
+    };
+
+    for slot in slots {
+        // every id in 0..m is present since there are m distinct ids
+        let i = slot.unwrap();
+        let t = targets[i];
+        if t as usize >= n {
+            return Err(invalid_data(format!("invalid edge target: {}", t)));
+        }
+        let (start, end) = (
+            geometry_offsets[i] as usize,
+            geometry_offsets[i + 1] as usize,
+        );
+        if start > end || end > point_count {
+            return Err(invalid_data(format!(
+                "invalid geometry offsets at edge {}",
+                i
+            )));
+        }
+        let data = EdgeData {
+            osm_way_id: WayId(osm_way_ids[i]),
+            osm_way_piece: osm_way_pieces[i],
+            name: string(names[i])?,
+            length: lengths[i],
+            street_type: string(street_types[i])?,
+            max_speed: max_speeds[i],
+            bidirectional: bidirectional[i] != 0,
+            geometry: (start..end)
+                .map(|p| {
+                    (
+                        from_decimicro(geometry[2 * p]),
+                        from_decimicro(geometry[2 * p + 1]),
+                    )
+                })
+                .collect(),
+        };
+        let is_bidirectional = data.bidirectional;
+        g.add_edge(
+            GraphNodeId(sources[i]),
+            GraphNodeId(t),
+            data,
+            is_bidirectional,
+        );
+    }
+
+    Ok(g)
+}
+
+// index of `s` in the string table, adding it if necessary
+fn intern<'a>(s: &'a str, strings: &mut Vec<&'a str>, ids: &mut HashMap<&'a str, u32>) -> u32 {
+    *ids.entry(s).or_insert_with(|| {
+        strings.push(s);
+        (strings.len() - 1) as u32
+    })
+}
+
+fn to_decimicro(x: f64) -> i32 {
+    (x * 1e7).round() as i32
+}
+
+fn from_decimicro(x: i32) -> f64 {
+    x as f64 * 1e-7
+}
+
+// TESTS
+#[cfg(test)]
+fn create_edge_data(
+    way: i64,
+    name: &str,
+    geometry: Vec<(f64, f64)>,
+    bidirectional: bool,
+) -> EdgeData {
+    EdgeData {
+        osm_way_id: WayId(way),
+        osm_way_piece: 0,
+        name: name.to_string(),
+        length: 12.345,
+        street_type: "residential".to_string(),
+        max_speed: 30,
+        bidirectional,
+        geometry,
+    }
+}
+
+#[test]
+fn should_round_trip_graph() {
+    let mut g: Graph<NodeData, EdgeData> = Graph {
+        nodes: Vec::new(),
+        edges: Vec::new(),
+    };
+    let coordinates = [(52.5, 13.4), (52.5000001, -13.4000001), (-1.0, 179.9999999)];
+    for (i, (lat, lon)) in coordinates.iter().enumerate() {
+        g.add_node(NodeData {
+            osm_id: NodeId(100 + i as i64),
+            lat: *lat,
+            lon: *lon,
+        });
+    }
+    // edges not grouped by source, to exercise the CSR reordering
+    let edges = [
+        (2, 0, true, "Hauptstraße"),
+        (0, 1, false, ""),
+        (1, 2, false, "Hauptstraße"),
+    ];
+    for (i, (s, t, bidirectional, name)) in edges.iter().enumerate() {
+        let geometry = vec![coordinates[*s], (0.5, 0.25), coordinates[*t]];
+        g.add_edge(
+            GraphNodeId(*s as u32),
+            GraphNodeId(*t as u32),
+            create_edge_data(i as i64 + 1, name, geometry, *bidirectional),
+            *bidirectional,
+        );
+    }
+
+    g.edges[1].data.osm_way_piece = 2;
+
+    let mut buffer = Vec::new();
+    write_graph(&g, &mut buffer).unwrap();
+    assert!(buffer.starts_with(MAGIC));
+
+    let read = read_graph(&mut buffer.as_slice()).unwrap();
+
+    assert!(read.nodes.len() == g.nodes.len());
+    for (a, b) in g.nodes.iter().zip(read.nodes.iter()) {
+        assert!(a.data.osm_id == b.data.osm_id);
+        assert!((a.data.lat - b.data.lat).abs() < 1e-9);
+        assert!((a.data.lon - b.data.lon).abs() < 1e-9);
+        assert!(a.get_edges() == b.get_edges());
+    }
+
+    assert!(read.edges.len() == g.edges.len());
+    for (a, b) in g.edges.iter().zip(read.edges.iter()) {
+        assert!(a.id == b.id && a.s == b.s && a.t == b.t);
+        assert!(a.data.osm_way_id == b.data.osm_way_id);
+        assert!(a.data.osm_way_piece == b.data.osm_way_piece);
+        assert!(a.data.name == b.data.name);
+        assert!(a.data.street_type == b.data.street_type);
+        assert!(a.data.length == b.data.length);
+        assert!(a.data.max_speed == b.data.max_speed);
+        assert!(a.data.bidirectional == b.data.bidirectional);
+        assert!(a.data.geometry.len() == b.data.geometry.len());
+        for (p, q) in a.data.geometry.iter().zip(b.data.geometry.iter()) {
+            assert!((p.0 - q.0).abs() < 1e-9 && (p.1 - q.1).abs() < 1e-9);
+        }
+    }
+}
+
+#[test]
+fn should_reject_invalid_input() {
+    let not_a_graph = b"# Road Graph File v.0.4".to_vec();
+    assert!(read_graph(&mut not_a_graph.as_slice()).is_err());
+
+    let mut wrong_version = MAGIC.to_vec();
+    wrong_version.extend_from_slice(&99u32.to_le_bytes());
+    assert!(read_graph(&mut wrong_version.as_slice()).is_err());
+
+    let mut truncated = MAGIC.to_vec();
+    truncated.extend_from_slice(&VERSION.to_le_bytes());
+    truncated.extend_from_slice(&5u32.to_le_bytes());
+    assert!(read_graph(&mut truncated.as_slice()).is_err());
+}
+
+#[test]
+fn should_reject_huge_counts_without_allocating() {
+    let header = |n: u32, m: u32, strings: u32| {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        data.extend_from_slice(&n.to_le_bytes());
+        data.extend_from_slice(&m.to_le_bytes());
+        data.extend_from_slice(&strings.to_le_bytes());
+        data
+    };
+
+    let huge_counts = header(u32::MAX, u32::MAX, u32::MAX);
+    let error = read_graph(&mut huge_counts.as_slice()).err().unwrap();
+    assert!(error.kind() == io::ErrorKind::UnexpectedEof);
+
+    let mut huge_string = header(0, 0, 1);
+    huge_string.extend_from_slice(&u32::MAX.to_le_bytes());
+    huge_string.extend_from_slice(b"abc");
+    let error = read_graph(&mut huge_string.as_slice()).err().unwrap();
+    assert!(error.kind() == io::ErrorKind::UnexpectedEof);
+
+    let huge_arrays = header(u32::MAX, u32::MAX, 0);
+    let error = read_graph(&mut huge_arrays.as_slice()).err().unwrap();
+    assert!(error.kind() == io::ErrorKind::UnexpectedEof);
+}
@@ -0,0 +1,435 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::time::Instant;
+
+use error::Error;
+use node_store::NodeStore;
+use osmpbfreader::{NodeId, Way};
+
+pub enum ClipRegion {
+    BoundingBox {
+        min_lon: f64,
+        min_lat: f64,
+        max_lon: f64,
+        max_lat: f64,
+    },
+    // rings of (lon, lat) points; a point is inside if it is inside of an
+    // outer ring and not inside of a hole
+    Polygon {
+        outer: Vec<Vec<(f64, f64)>>,
+        holes: Vec<Vec<(f64, f64)>>,
+    },
+}
+
+// what to do with ways crossing the boundary of the clip region
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ClipPolicy {
+    // keep the complete way if at least one of its nodes is inside
+    Keep,
+    // keep the parts of the way inside, cut where it crosses the boundary
+    Cut,
+    // keep only ways that are completely inside
+    Drop,
+}
+
+pub struct Clip {
+    pub region: ClipRegion,
+    pub policy: ClipPolicy,
+}
+
+pub fn get_clip_policy(policy: &str) -> Result<ClipPolicy, Error> {
+    match policy {
+        "keep" => Ok(ClipPolicy::Keep),
+        "cut" => Ok(ClipPolicy::Cut),
+        "drop" => Ok(ClipPolicy::Drop),
+        _ => Err(Error::Argument(format!(
+            "did not recognize clip policy: {}",
+            policy
+        ))),
+    }
+}
+
+// parses "minlon,minlat,maxlon,maxlat"
+pub fn parse_bbox(bbox: &str) -> Result<ClipRegion, Error> {
+    let values = bbox
+        .split(',')
+        .map(|x| {
+            x.trim()
+                .parse::<f64>()
+                .map_err(|_| Error::Argument(format!("invalid bounding box value: {}", x)))
+        })
+        .collect::<Result<Vec<f64>, Error>>()?;
+
+    if values.len() != 4 || values[0] > values[2] || values[1] > values[3] {
+        return Err(Error::Argument(format!(
+            "bounding box has to be given as minlon,minlat,maxlon,maxlat: {}",
+            bbox
+        )));
+    }
+
+    Ok(ClipRegion::BoundingBox {
+        min_lon: values[0],
+        min_lat: values[1],
+        max_lon: values[2],
+        max_lat: values[3],
+    })
+}
+
+pub fn read_poly_file(filename: &str) -> Result<ClipRegion, Error> {
+    let mut file = File::open(filename)?;
+    let mut file_content = String::new();
+    file.read_to_string(&mut file_content)?;
+
+    parse_poly(&file_content)
+}
+
+// parses the Osmosis polygon filter file format: a name line, followed by
+// sections of "lon lat" lines each terminated by END; sections whose name
+// starts with '!' are holes. The file is terminated by another END.
+pub fn parse_poly(content: &str) -> Result<ClipRegion, Error> {
+    let mut outer = Vec::new();
+    let mut holes = Vec::new();
+
+    let mut lines = content.lines().map(|l| l.trim()).filter(|l| !l.is_empty());
+    lines.next(); // name of the polygon
+
+    while let Some(section) = lines.next() {
+        if section == "END" {
+            break;
+        }
+
+        let mut ring = Vec::new();
+        for line in lines.by_ref() {
+            if line == "END" {
+                break;
+            }
+            let values = line
+                .split_whitespace()
+                .map(|x| x.parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>()
+                .ok()
+                .filter(|values| values.len() == 2)
+                .ok_or_else(|| Error::Data(format!("invalid polygon coordinate: {}", line)))?;
+            ring.push((values[0], values[1]));
+        }
+
+        if section.starts_with('!') {
+            holes.push(ring);
+        } else {
+            outer.push(ring);
+        }
+    }
+
+    Ok(ClipRegion::Polygon { outer, holes })
+}
+
+impl ClipRegion {
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        match self {
+            ClipRegion::BoundingBox {
+                min_lon,
+                min_lat,
+                max_lon,
+                max_lat,
+            } => *min_lon <= lon && lon <= *max_lon && *min_lat <= lat && lat <= *max_lat,
+            ClipRegion::Polygon { outer, holes } => {
+                outer.iter().any(|r| ring_contains(r, lon, lat))
+                    && !holes.iter().any(|r| ring_contains(r, lon, lat))
+            }
+        }
+    }
+
+    // the first point where the segment from the (lat, lon) point a to b
+    // crosses the boundary, if it does so after leaving a
+    pub fn crossing(&self, a: (f64, f64), b: (f64, f64)) -> Option<(f64, f64)> {
+        let rings = match self {
+            ClipRegion::BoundingBox {
+                min_lon,
+                min_lat,
+                max_lon,
+                max_lat,
+            } => vec![vec![
+                (*min_lon, *min_lat),
+                (*max_lon, *min_lat),
+                (*max_lon, *max_lat),
+                (*min_lon, *max_lat),
+            ]],
+            ClipRegion::Polygon { outer, holes } => {
+                outer.iter().chain(holes.iter()).cloned().collect()
+            }
+        };
+
+        // the segment is p + t * r, a side of a ring q + u * s, both in (lon, lat)
+        let cross = |x: (f64, f64), y: (f64, f64)| x.0 * y.1 - x.1 * y.0;
+        let p = (a.1, a.0);
+        let r = (b.1 - a.1, b.0 - a.0);
+        let mut first: Option<f64> = None;
+        for ring in &rings {
+            for i in 0..ring.len() {
+                let q = ring[i];
+                let next = ring[(i + 1) % ring.len()];
+                let s = (next.0 - q.0, next.1 - q.1);
+                let denominator = cross(r, s);
+                if denominator == 0.0 {
+                    continue;
+                }
+                let qp = (q.0 - p.0, q.1 - p.1);
+                let t = cross(qp, s) / denominator;
+                let u = cross(qp, r) / denominator;
+                if t > 0.0 && t <= 1.0 && (0.0..=1.0).contains(&u) && first.is_none_or(|f| t < f) {
+                    first = Some(t);
+                }
+            }
+        }
+
+        first.map(|t| (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t))
+    }
+}
+
+// even-odd rule
+pub fn ring_contains(ring: &[(f64, f64)], x: f64, y: f64) -> bool {
+    let mut inside = false;
+    let mut j = ring.len().wrapping_sub(1);
+    for i in 0..ring.len() {
+        let (xi, yi) = ring[i];
+        let (xj, yj) = ring[j];
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+// nodes without coordinates count as outside of the region; ways are cut at
+// new nodes with negative ids, below those of the input
+pub fn clip_ways(ways: Vec<Way>, nodes: &mut NodeStore, clip: &Clip) -> Vec<Way> {
+    let ways_initially = ways.len();
+    let now = Instant::now();
+
+    let mut next_id = ways
+        .iter()
+        .flat_map(|w| w.nodes.iter())
+        .map(|id| id.0)
+        .min()
+        .unwrap_or(0)
+        .min(0)
+        - 1;
+    let mut new_nodes: Vec<(NodeId, f64, f64)> = Vec::new();
+
+    let mut result = Vec::new();
+    for way in ways {
+        let inside: Vec<bool> = way
+            .nodes
+            .iter()
+            .map(|id| {
+                nodes
+                    .get(id)
+                    .map(|(lat, lon)| clip.region.contains(lat, lon))
+                    .unwrap_or(false)
+            })
+            .collect();
+
+        if inside.iter().all(|x| *x) {
+            result.push(way);
+            continue;
+        }
+
+        match clip.policy {
+            ClipPolicy::Keep => {
+                if inside.iter().any(|x| *x) {
+                    result.push(way);
+                }
+            }
+            ClipPolicy::Cut => {
+                // the node where the way leaves the region between the nodes
+                // inside and outside
+                let mut cut = |inside: usize, outside: usize| {
+                    let a = nodes.get(&way.nodes[inside])?;
+                    let b = nodes.get(&way.nodes[outside])?;
+                    let (lat, lon) = clip.region.crossing(a, b)?;
+                    let id = NodeId(next_id);
+                    next_id -= 1;
+                    new_nodes.push((id, lat, lon));
+                    Some(id)
+                };
+
+                let mut start = 0;
+                while start < inside.len() {
+                    if !inside[start] {
+                        start += 1;
+                        continue;
+                    }
+                    let mut end = start;
+                    while end + 1 < inside.len() && inside[end + 1] {
+                        end += 1;
+                    }
+
+                    let mut piece = Vec::new();
+                    if start > 0 {
+                        piece.extend(cut(start, start - 1));
+                    }
+                    piece.extend_from_slice(&way.nodes[start..=end]);
+                    if end + 1 < inside.len() {
+                        piece.extend(cut(end, end + 1));
+                    }
+                    if piece.len() >= 2 {
+                        result.push(Way {
+                            id: way.id,
+                            tags: way.tags.clone(),
+                            nodes: piece,
+                        });
+                    }
+                    start = end + 1;
+                }
+            }
+            ClipPolicy::Drop => {}
+        }
+    }
+
+    if !new_nodes.is_empty() {
+        nodes.insert(&new_nodes);
+    }
+
+    info!("clipped ways to region: {}s", now.elapsed().as_secs());
+    info!(
+        "#ways now: {}/{} (policy: {:?})",
+        result.len(),
+        ways_initially,
+        clip.policy
+    );
+
+    result
+}
+
+// TESTS
+#[cfg(test)]
+use osmpbfreader::{Tags, WayId};
+
+#[cfg(test)]
+const TEST_POLY: &str = "test_polygon
+1
+   0.0   0.0
+   10.0  0.0
+   10.0  10.0
+   0.0   10.0
+END
+!2
+   4.0   4.0
+   6.0   4.0
+   6.0   6.0
+   4.0   6.0
+END
+END
+";
+
+#[cfg(test)]
+fn create_nodes(coordinates: &[(i64, f64, f64)]) -> NodeStore {
+    let mut nodes = NodeStore::with_ids(coordinates.iter().map(|c| NodeId(c.0)).collect());
+    for (id, lat, lon) in coordinates {
+        nodes.set(NodeId(*id), (lat * 1e7) as i32, (lon * 1e7) as i32);
+    }
+    nodes
+}
+
+#[cfg(test)]
+fn create_way(id: i64, node_ids: &[i64]) -> Way {
+    Way {
+        id: WayId(id),
+        tags: Tags::new(),
+        nodes: node_ids.iter().map(|&x| NodeId(x)).collect(),
+    }
+}
+
+#[test]
+fn should_parse_bbox() {
+    let region = parse_bbox("13.0,52.0,14.0,53.0").unwrap();
+
+    assert!(region.contains(52.5, 13.5));
+    assert!(!region.contains(13.5, 52.5));
+    assert!(!region.contains(52.5, 12.9));
+}
+
+#[test]
+fn should_reject_invalid_clip_regions() {
+    let error = |result: Result<ClipRegion, Error>| result.err().unwrap().to_string();
+    assert!(
+        error(parse_bbox("13.0,52.0,x,53.0")) == "invalid argument: invalid bounding box value: x"
+    );
+    assert!(error(parse_bbox("14.0,52.0,13.0,53.0")).ends_with("14.0,52.0,13.0,53.0"));
+    assert!(
+        error(parse_poly("test\n1\n  1.0 2.0 3.0\nEND\nEND\n"))
+            == "inconsistent data: invalid polygon coordinate: 1.0 2.0 3.0"
+    );
+}
+
+#[test]
+fn should_parse_poly_with_holes() {
+    let region = parse_poly(TEST_POLY).unwrap();
+
+    assert!(region.contains(1.0, 1.0));
+    assert!(region.contains(9.0, 5.0));
+    assert!(!region.contains(5.0, 5.0));
+    assert!(!region.contains(11.0, 5.0));
+    assert!(!region.contains(-1.0, -1.0));
+}
+
+#[test]
+fn should_clip_ways_according_to_policy() {
+    // nodes 1, 2, 4 are inside, 3 and 5 are outside, 6 is unknown
+    let nodes = || {
+        create_nodes(&[
+            (1, 1.0, 1.0),
+            (2, 2.0, 2.0),
+            (3, 20.0, 20.0),
+            (4, 3.0, 3.0),
+            (5, 30.0, 30.0),
+        ])
+    };
+    let ways = || {
+        vec![
+            create_way(1, &[1, 2]),
+            create_way(2, &[1, 2, 3, 4]),
+            create_way(3, &[3, 5]),
+            create_way(4, &[2, 1, 6]),
+        ]
+    };
+    let clip = |policy| Clip {
+        region: parse_bbox("0,0,10,10").unwrap(),
+        policy,
+    };
+
+    let keep = clip_ways(ways(), &mut nodes(), &clip(ClipPolicy::Keep));
+    let mut cut_nodes = nodes();
+    let cut = clip_ways(ways(), &mut cut_nodes, &clip(ClipPolicy::Cut));
+    let drop = clip_ways(ways(), &mut nodes(), &clip(ClipPolicy::Drop));
+
+    let ids = |ways: &[Way]| ways.iter().map(|w| w.id.0).collect::<Vec<i64>>();
+    assert!(ids(&keep) == vec![1, 2, 4]);
+    assert!(ids(&cut) == vec![1, 2, 2, 4]);
+    assert!(ids(&drop) == vec![1]);
+
+    assert!(keep[1].nodes.len() == 4);
+    // way 2 leaves the region between 2 and 3 and enters it again between 3
+    // and 4, both times at its corner (10, 10); node 6 has no coordinates
+    assert!(cut[1].nodes == vec![NodeId(1), NodeId(2), NodeId(-1)]);
+    assert!(cut[2].nodes == vec![NodeId(-2), NodeId(4)]);
+    assert!(cut[3].nodes == vec![NodeId(2), NodeId(1)]);
+    for id in [-1, -2].iter() {
+        let (lat, lon) = cut_nodes.get(&NodeId(*id)).unwrap();
+        assert!((lat - 10.0).abs() < 1e-6 && (lon - 10.0).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn should_find_boundary_crossings() {
+    let bbox = parse_bbox("0,0,10,10").unwrap();
+    let (lat, lon) = bbox.crossing((5.0, 5.0), (5.0, 15.0)).unwrap();
+    assert!((lat - 5.0).abs() < 1e-9 && (lon - 10.0).abs() < 1e-9);
+    assert!(bbox.crossing((5.0, 5.0), (6.0, 6.0)).is_none());
+
+    // leaving through the hole of the polygon
+    let polygon = parse_poly(TEST_POLY).unwrap();
+    let (lat, lon) = polygon.crossing((5.0, 2.0), (5.0, 5.0)).unwrap();
+    assert!((lat - 5.0).abs() < 1e-9 && (lon - 4.0).abs() < 1e-9);
+}
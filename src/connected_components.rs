@@ -0,0 +1,280 @@
+use std::time::Instant;
+
+use error::Error;
+use graph::{Graph, GraphNodeId};
+use network_type::NetworkType;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ComponentType {
+    Weak,
+    Strong,
+}
+
+pub fn get_component_type(component_type: &str) -> Result<ComponentType, Error> {
+    match component_type {
+        "weak" | "w" => Ok(ComponentType::Weak),
+        "strong" | "s" => Ok(ComponentType::Strong),
+        _ => Err(Error::Argument(format!(
+            "did not recognize component type: {}",
+            component_type
+        ))),
+    }
+}
+
+// oneway streets make car networks directed, so only strongly connected
+// components guarantee that every node can be reached from every other node
+pub fn default_component_type(network_type: &NetworkType) -> ComponentType {
+    match network_type {
+        NetworkType::Car => ComponentType::Strong,
+        NetworkType::Pedestrian | NetworkType::Bicycle => ComponentType::Weak,
+    }
+}
+
+pub fn reduce_to_largest_component<N, E>(
+    g: Graph<N, E>,
+    component_type: ComponentType,
+) -> Graph<N, E> {
+    let now = Instant::now();
+
+    let components = match component_type {
+        ComponentType::Weak => weakly_connected_components(&g),
+        ComponentType::Strong => strongly_connected_components(&g),
+    };
+
+    let mut component_sizes = Vec::new();
+    for c in &components {
+        let c = *c as usize;
+        if c >= component_sizes.len() {
+            component_sizes.resize(c + 1, 0);
+        }
+        component_sizes[c] += 1;
+    }
+
+    let largest = component_sizes
+        .iter()
+        .enumerate()
+        .max_by_key(|&(i, size)| (size, std::cmp::Reverse(i)))
+        .map(|(i, _)| i as u32);
+
+    let keep: Vec<bool> = components.iter().map(|c| Some(*c) == largest).collect();
+
+    let nodes_initially = g.nodes.len();
+    let g = g.retain_nodes(&keep);
+
+    info!(
+        "reduced graph to largest {:?} component: {}s",
+        component_type,
+        now.elapsed().as_secs()
+    );
+    info!(
+        "#components: {}, #nodes now: {}/{}, #edges now: {}",
+        component_sizes.len(),
+        g.nodes.len(),
+        nodes_initially,
+        g.edges.len()
+    );
+
+    g
+}
+
+// component id per node, ignoring edge directions
+pub fn weakly_connected_components<N, E>(g: &Graph<N, E>) -> Vec<u32> {
+    let mut neighbors: Vec<Vec<GraphNodeId>> = vec![Vec::new(); g.nodes.len()];
+    for edge in &g.edges {
+        neighbors[edge.s.0 as usize].push(edge.t);
+        neighbors[edge.t.0 as usize].push(edge.s);
+    }
+
+    let mut components = vec![u32::MAX; g.nodes.len()];
+    let mut next_component = 0;
+    let mut stack = Vec::new();
+
+    for start in 0..g.nodes.len() {
+        if components[start] != u32::MAX {
+            continue;
+        }
+
+        components[start] = next_component;
+        stack.push(start);
+        while let Some(u) = stack.pop() {
+            for v in &neighbors[u] {
+                let v = v.0 as usize;
+                if components[v] == u32::MAX {
+                    components[v] = next_component;
+                    stack.push(v);
+                }
+            }
+        }
+        next_component += 1;
+    }
+
+    components
+}
+
+// component id per node following edge directions; iterative Kosaraju so that
+// large graphs cannot overflow the call stack
+pub fn strongly_connected_components<N, E>(g: &Graph<N, E>) -> Vec<u32> {
+    let n = g.nodes.len();
+    let forward: Vec<Vec<usize>> = (0..n)
+        .map(|u| {
+            g.out_neighbors(GraphNodeId(u as u32))
+                .map(|v| v.0 as usize)
+                .collect()
+        })
+        .collect();
+
+    let mut backward: Vec<Vec<usize>> = vec![Vec::new(); n];
+    for (u, targets) in forward.iter().enumerate() {
+        for v in targets {
+            backward[*v].push(u);
+        }
+    }
+
+    // first pass: order nodes by DFS finishing time
+    let mut visited = vec![false; n];
+    let mut finish_order = Vec::with_capacity(n);
+    let mut stack: Vec<(usize, usize)> = Vec::new();
+
+    for start in 0..n {
+        if visited[start] {
+            continue;
+        }
+
+        visited[start] = true;
+        stack.push((start, 0));
+        while let Some((u, next)) = stack.pop() {
+            if next < forward[u].len() {
+                stack.push((u, next + 1));
+                let v = forward[u][next];
+                if !visited[v] {
+                    visited[v] = true;
+                    stack.push((v, 0));
+                }
+            } else {
+                finish_order.push(u);
+            }
+        }
+    }
+
+    // second pass: collect components on the reversed graph
+    let mut components = vec![u32::MAX; n];
+    let mut next_component = 0;
+    let mut stack = Vec::new();
+
+    for start in finish_order.into_iter().rev() {
+        if components[start] != u32::MAX {
+            continue;
+        }
+
+        components[start] = next_component;
+        stack.push(start);
+        while let Some(u) = stack.pop() {
+            for v in &backward[u] {
+                if components[*v] == u32::MAX {
+                    components[*v] = next_component;
+                    stack.push(*v);
+                }
+            }
+        }
+        next_component += 1;
+    }
+
+    components
+}
+
+// TESTS
+#[cfg(test)]
+fn create_graph(edges: &[(u32, u32, bool)], num_nodes: u32) -> Graph<u32, ()> {
+    let mut g = Graph {
+        nodes: Vec::new(),
+        edges: Vec::new(),
+    };
+    for i in 0..num_nodes {
+        g.add_node(i);
+    }
+    for (s, t, bidirectional) in edges {
+        g.add_edge(GraphNodeId(*s), GraphNodeId(*t), (), *bidirectional);
+    }
+    g
+}
+
+#[test]
+fn should_find_weak_components() {
+    let g = create_graph(&[(0, 1, false), (2, 1, false), (3, 4, true)], 6);
+
+    let components = weakly_connected_components(&g);
+
+    assert!(components[0] == components[1]);
+    assert!(components[1] == components[2]);
+    assert!(components[3] == components[4]);
+    assert!(components[0] != components[3]);
+    assert!(components[5] != components[0] && components[5] != components[3]);
+}
+
+#[test]
+fn should_find_strong_components() {
+    // 0 -> 1 -> 2 -> 0 is a cycle, 2 -> 3 is a dead end, 3 <-> 4
+    let g = create_graph(
+        &[(0, 1, false), (1, 2, false), (2, 0, false), (2, 3, false), (3, 4, true)],
+        5,
+    );
+
+    let components = strongly_connected_components(&g);
+
+    assert!(components[0] == components[1]);
+    assert!(components[1] == components[2]);
+    assert!(components[3] == components[4]);
+    assert!(components[0] != components[3]);
+}
+
+#[test]
+fn should_reduce_to_largest_component_and_renumber() {
+    let g = create_graph(&[(0, 1, true), (2, 3, true), (3, 4, false), (4, 2, true)], 5);
+
+    let g = reduce_to_largest_component(g, ComponentType::Weak);
+
+    assert!(g.nodes.len() == 3);
+    assert!(g.edges.len() == 3);
+    assert!(g.nodes.iter().map(|n| n.data).collect::<Vec<_>>() == vec![2, 3, 4]);
+    assert!(g.edges[0].s == GraphNodeId(0) && g.edges[0].t == GraphNodeId(1));
+    assert!(g.nodes[1].get_edges().len() == 2);
+    assert!(g.nodes[2].get_edges().len() == 1);
+}
+
+#[test]
+fn should_drop_oneway_dead_ends_from_largest_strong_component() {
+    // 0 <-> 1 <-> 2 <-> 0 is a triangle, 2 -> 3 -> 4 can never lead back
+    let g = create_graph(
+        &[
+            (0, 1, true),
+            (1, 2, true),
+            (2, 0, true),
+            (2, 3, false),
+            (3, 4, false),
+        ],
+        5,
+    );
+
+    let g = reduce_to_largest_component(g, ComponentType::Strong);
+
+    assert!(g.nodes.iter().map(|n| n.data).collect::<Vec<_>>() == vec![0, 1, 2]);
+    assert!(g.edges.len() == 3);
+}
+
+#[test]
+fn should_use_strong_components_only_for_cars() {
+    assert!(default_component_type(&NetworkType::Car) == ComponentType::Strong);
+    assert!(default_component_type(&NetworkType::Pedestrian) == ComponentType::Weak);
+    assert!(default_component_type(&NetworkType::Bicycle) == ComponentType::Weak);
+}
+
+#[test]
+fn should_handle_long_paths_without_recursion() {
+    let n = 500_000;
+    let edges: Vec<(u32, u32, bool)> = (0..n).map(|i| (i, (i + 1) % n, false)).collect();
+    let g = create_graph(&edges, n);
+
+    let components = strongly_connected_components(&g);
+
+    assert!(components.iter().all(|c| *c == components[0]));
+}
@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use graph::{Graph, GraphEdgeId, GraphNodeId};
+use graph_data::{EdgeData, EdgeDataDescription, NodeData};
+use osmpbfreader::WayId;
+
+pub struct ContractedEdgeData {
+    pub data: EdgeData,
+    // ids of the nodes of the uncontracted graph along this edge, including both end points
+    pub original_nodes: Vec<GraphNodeId>,
+    // OSM way of every merged edge, in the order of original_nodes
+    pub original_ways: Vec<WayId>,
+}
+
+impl AsRef<EdgeData> for ContractedEdgeData {
+    fn as_ref(&self) -> &EdgeData {
+        &self.data
+    }
+}
+
+impl EdgeDataDescription for ContractedEdgeData {
+    fn name(&self) -> String {
+        self.data.name()
+    }
+
+    fn osm_id(&self) -> Option<i64> {
+        self.data.osm_id()
+    }
+
+    fn description(&self) -> String {
+        self.data.description()
+    }
+
+    fn length_and_travel_time(&self) -> Option<(f64, f64)> {
+        self.data.length_and_travel_time()
+    }
+
+    fn csv_fields(&self) -> Vec<String> {
+        self.data.csv_fields()
+    }
+}
+
+// merges chains of degree 2 nodes into single edges, as long as the merged
+// edges agree on direction and attributes; the merged edge keeps the OSM
+// way id of its first edge and lists all of them in original_ways
+pub fn contract(g: &Graph<NodeData, EdgeData>) -> Graph<NodeData, ContractedEdgeData> {
+    let now = Instant::now();
+
+    let mut incident_edges: Vec<Vec<GraphEdgeId>> = vec![Vec::new(); g.nodes.len()];
+    for edge in &g.edges {
+        incident_edges[edge.s.0 as usize].push(edge.id);
+        incident_edges[edge.t.0 as usize].push(edge.id);
+    }
+
+    let contractible: Vec<bool> = g
+        .nodes
+        .iter()
+        .map(|n| is_contractible(g, n.id, &incident_edges[n.id.0 as usize]))
+        .collect();
+
+    let mut contracted = Graph {
+        nodes: Vec::new(),
+        edges: Vec::new(),
+    };
+    let mut node_map: HashMap<GraphNodeId, GraphNodeId> = HashMap::new();
+    let mut edge_used = vec![false; g.edges.len()];
+
+    for node in &g.nodes {
+        if contractible[node.id.0 as usize] {
+            continue;
+        }
+        get_or_add_node(g, &mut contracted, &mut node_map, node.id);
+
+        for e in &incident_edges[node.id.0 as usize] {
+            if edge_used[e.0 as usize] {
+                continue;
+            }
+
+            let mut chain = vec![*e];
+            let mut chain_nodes = vec![node.id];
+            edge_used[e.0 as usize] = true;
+            let mut current = g.edge(*e).other(node.id);
+
+            while contractible[current.0 as usize] {
+                chain_nodes.push(current);
+                let last = *chain.last().unwrap();
+                let next = incident_edges[current.0 as usize]
+                    .iter()
+                    .find(|x| **x != last)
+                    .unwrap();
+                edge_used[next.0 as usize] = true;
+                chain.push(*next);
+                current = g.edge(*next).other(current);
+            }
+            chain_nodes.push(current);
+
+            add_chain(g, &mut contracted, &mut node_map, chain, chain_nodes);
+        }
+    }
+
+    // edges on cycles consisting only of contractible nodes are kept as they are
+    for edge in &g.edges {
+        if !edge_used[edge.id.0 as usize] {
+            add_chain(
+                g,
+                &mut contracted,
+                &mut node_map,
+                vec![edge.id],
+                vec![edge.s, edge.t],
+            );
+        }
+    }
+
+    info!(
+        "contracted graph to {} edges and {} nodes: {}s",
+        contracted.edges.len(),
+        contracted.nodes.len(),
+        now.elapsed().as_secs()
+    );
+
+    contracted
+}
+
+fn is_contractible(
+    g: &Graph<NodeData, EdgeData>,
+    id: GraphNodeId,
+    incident_edges: &[GraphEdgeId],
+) -> bool {
+    if incident_edges.len() != 2 || incident_edges[0] == incident_edges[1] {
+        return false;
+    }
+
+    let a = g.edge(incident_edges[0]);
+    let b = g.edge(incident_edges[1]);
+
+    if a.s == a.t || b.s == b.t || !same_attributes(&a.data, &b.data) {
+        return false;
+    }
+
+    // a chain of oneway edges has to pass through the node
+    a.data.bidirectional || (a.t == id) != (b.t == id)
+}
+
+fn same_attributes(a: &EdgeData, b: &EdgeData) -> bool {
+    a.name == b.name
+        && a.street_type == b.street_type
+        && a.max_speed == b.max_speed
+        && a.bidirectional == b.bidirectional
+}
+
+fn get_or_add_node(
+    g: &Graph<NodeData, EdgeData>,
+    contracted: &mut Graph<NodeData, ContractedEdgeData>,
+    node_map: &mut HashMap<GraphNodeId, GraphNodeId>,
+    id: GraphNodeId,
+) -> GraphNodeId {
+    *node_map.entry(id).or_insert_with(|| {
+        let data = &g.nodes[id.0 as usize].data;
+        contracted.add_node(NodeData {
+            osm_id: data.osm_id,
+            lat: data.lat,
+            lon: data.lon,
+        })
+    })
+}
+
+// merges the consecutive edges of `chain` running along `chain_nodes`
+fn add_chain(
+    g: &Graph<NodeData, EdgeData>,
+    contracted: &mut Graph<NodeData, ContractedEdgeData>,
+    node_map: &mut HashMap<GraphNodeId, GraphNodeId>,
+    chain: Vec<GraphEdgeId>,
+    mut chain_nodes: Vec<GraphNodeId>,
+) {
+    let first = &g.edge(chain[0]).data;
+    let mut geometry: Vec<(f64, f64)> = Vec::new();
+    let mut length = 0.0;
+    let mut ways = Vec::with_capacity(chain.len());
+
+    for (e, s) in chain.iter().zip(chain_nodes.iter()) {
+        let edge = g.edge(*e);
+        let mut points = edge.data.geometry.clone();
+        if edge.s != *s {
+            points.reverse();
+        }
+        if !geometry.is_empty() {
+            points.remove(0);
+        }
+        geometry.extend(points);
+        length += edge.data.length;
+        ways.push(edge.data.osm_way_id);
+    }
+
+    // oneway chains have to be stored in driving direction
+    if !first.bidirectional && g.edge(chain[0]).s != chain_nodes[0] {
+        chain_nodes.reverse();
+        geometry.reverse();
+        ways.reverse();
+    }
+
+    let s = get_or_add_node(g, contracted, node_map, chain_nodes[0]);
+    let t = get_or_add_node(g, contracted, node_map, *chain_nodes.last().unwrap());
+
+    let data = ContractedEdgeData {
+        data: EdgeData {
+            osm_way_id: first.osm_way_id,
+            osm_way_piece: first.osm_way_piece,
+            name: first.name.clone(),
+            length,
+            street_type: first.street_type.clone(),
+            max_speed: first.max_speed,
+            bidirectional: first.bidirectional,
+            geometry,
+        },
+        original_nodes: chain_nodes,
+        original_ways: ways,
+    };
+    contracted.add_edge(s, t, data, first.bidirectional);
+}
+
+// TESTS
+#[cfg(test)]
+use osmpbfreader::NodeId;
+
+#[cfg(test)]
+fn create_graph(edges: &[(u32, u32, bool, &str)], num_nodes: u32) -> Graph<NodeData, EdgeData> {
+    let mut g = Graph {
+        nodes: Vec::new(),
+        edges: Vec::new(),
+    };
+    for i in 0..num_nodes {
+        g.add_node(NodeData {
+            osm_id: NodeId(i as i64),
+            lat: i as f64,
+            lon: 0.0,
+        });
+    }
+    for (s, t, bidirectional, name) in edges {
+        let data = EdgeData {
+            osm_way_id: WayId(0),
+            osm_way_piece: 0,
+            name: name.to_string(),
+            length: 1.0,
+            street_type: "residential".to_string(),
+            max_speed: 30,
+            bidirectional: *bidirectional,
+            geometry: vec![(*s as f64, 0.0), (*t as f64, 0.0)],
+        };
+        g.add_edge(GraphNodeId(*s), GraphNodeId(*t), data, *bidirectional);
+    }
+    g
+}
+
+#[cfg(test)]
+fn original_ids(edge_data: &ContractedEdgeData) -> Vec<u32> {
+    edge_data.original_nodes.iter().map(|x| x.0).collect()
+}
+
+#[test]
+fn should_contract_chain_of_degree_two_nodes() {
+    // 0 - 1 - 2 - 3 with a branch 3 - 4 and 3 - 5
+    let g = create_graph(
+        &[
+            (1, 0, true, "a"),
+            (1, 2, true, "a"),
+            (2, 3, true, "a"),
+            (3, 4, true, "a"),
+            (3, 5, true, "a"),
+        ],
+        6,
+    );
+
+    let c = contract(&g);
+
+    assert!(c.nodes.len() == 4);
+    assert!(c.edges.len() == 3);
+    assert!(original_ids(&c.edges[0].data) == vec![0, 1, 2, 3]);
+    assert!(c.edges[0].data.data.length == 3.0);
+    assert!(c.edges[0].data.data.geometry == vec![(0.0, 0.0), (1.0, 0.0), (2.0, 0.0), (3.0, 0.0)]);
+}
+
+#[test]
+fn should_not_contract_when_attributes_differ() {
+    let g = create_graph(&[(0, 1, true, "a"), (1, 2, true, "b")], 3);
+
+    let c = contract(&g);
+
+    assert!(c.nodes.len() == 3);
+    assert!(c.edges.len() == 2);
+}
+
+#[test]
+fn should_contract_oneway_chain_in_driving_direction() {
+    let g = create_graph(&[(2, 1, false, "a"), (1, 0, false, "a")], 3);
+
+    let c = contract(&g);
+
+    assert!(c.edges.len() == 1);
+    assert!(original_ids(&c.edges[0].data) == vec![2, 1, 0]);
+    assert!(c.nodes[c.edges[0].s.0 as usize].data.lat == 2.0);
+    assert!(c.nodes[c.edges[0].t.0 as usize].data.lat == 0.0);
+}
+
+#[test]
+fn should_not_contract_opposing_oneways() {
+    let g = create_graph(&[(0, 1, false, "a"), (2, 1, false, "a")], 3);
+
+    let c = contract(&g);
+
+    assert!(c.edges.len() == 2);
+}
+
+#[test]
+fn should_keep_isolated_cycles() {
+    let g = create_graph(
+        &[(0, 1, true, "a"), (1, 2, true, "a"), (2, 0, true, "a")],
+        3,
+    );
+
+    let c = contract(&g);
+
+    assert!(c.nodes.len() == 3);
+    assert!(c.edges.len() == 3);
+}
+
+#[test]
+fn should_contract_across_ways_and_keep_their_ids() {
+    let mut g = create_graph(
+        &[(0, 1, true, "a"), (1, 2, true, "a"), (2, 3, true, "a")],
+        4,
+    );
+    g.edges[1].data.osm_way_piece = 1;
+    g.edges[2].data.osm_way_id = WayId(1);
+
+    let c = contract(&g);
+
+    assert!(c.edges.len() == 1);
+    assert!(original_ids(&c.edges[0].data) == vec![0, 1, 2, 3]);
+    assert!(c.edges[0].data.original_ways == vec![WayId(0), WayId(0), WayId(1)]);
+    assert!(c.edges[0].data.osm_id() == Some(0));
+}
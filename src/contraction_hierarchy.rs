@@ -0,0 +1,988 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::time::Instant;
+
+use error::Error;
+use graph::{Graph, GraphEdgeId, GraphNodeId};
+use graph_data::{EdgeData, NodeData};
+use routing::{edge_cost, Cost, Path};
+use util;
+use util::{invalid_data, read_array, read_f64, read_u32, read_u64, read_u8, write_u32};
+
+// Contraction Hierarchy File, all values little-endian:
+//
+//   magic                "ROADCH\0\0"
+//   version              u32
+//   cost                 u8, 0 for distance and 1 for travel time
+//   n, m                 u32 each, nodes and edges of the graph
+//   checksum             u64, FNV-1a over end points, direction and max_speed of every edge
+//   rank                 n x u32
+//   up arcs              (n + 1) x u32 offsets, then per arc (u32 node, f64 weight, u8 kind, u32 via)
+//   down arcs            as up arcs
+//
+// kind 0: via is the id of the original edge, kind 1: via is the middle node
+// of a shortcut
+pub const MAGIC: &[u8; 8] = b"ROADCH\0\0";
+pub const VERSION: u32 = 2;
+
+// settled nodes after which a witness search gives up and assumes there is no witness
+const WITNESS_SEARCH_LIMIT: usize = 500;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Via {
+    Edge(GraphEdgeId),
+    // shortcut over a node of lower rank
+    Node(GraphNodeId),
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Arc {
+    pub node: GraphNodeId,
+    pub weight: f64,
+    pub via: Via,
+}
+
+pub struct ContractionHierarchy {
+    pub cost: Cost,
+    node_count: u32,
+    edge_count: u32,
+    checksum: u64,
+    rank: Vec<u32>,
+    // arcs v -> x to nodes of higher rank, stored at v
+    up: Vec<Vec<Arc>>,
+    // arcs x -> v from nodes of higher rank, stored at v with node x
+    down: Vec<Vec<Arc>>,
+}
+
+#[derive(PartialEq)]
+struct State {
+    key: f64,
+    node: u32,
+}
+
+impl Eq for State {}
+
+// reversed, so that BinaryHeap pops the smallest key first
+impl Ord for State {
+    fn cmp(&self, other: &State) -> Ordering {
+        other
+            .key
+            .partial_cmp(&self.key)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| other.node.cmp(&self.node))
+    }
+}
+
+impl PartialOrd for State {
+    fn partial_cmp(&self, other: &State) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// bounded Dijkstra over the not yet contracted nodes, reusing its distance
+// array between searches
+struct WitnessSearch {
+    distances: Vec<f64>,
+    touched: Vec<u32>,
+}
+
+impl WitnessSearch {
+    // distances from `s` to the nodes reachable without passing `ignore`,
+    // as long as they are at most `limit`
+    fn run(&mut self, out_arcs: &[Vec<Arc>], contracted: &[bool], s: u32, ignore: u32, limit: f64) {
+        for v in self.touched.drain(..) {
+            self.distances[v as usize] = f64::INFINITY;
+        }
+
+        let mut heap = BinaryHeap::new();
+        self.distances[s as usize] = 0.0;
+        self.touched.push(s);
+        heap.push(State { key: 0.0, node: s });
+
+        let mut settled = 0;
+        while let Some(State { key, node }) = heap.pop() {
+            if key > self.distances[node as usize] {
+                continue;
+            }
+            if key > limit || settled >= WITNESS_SEARCH_LIMIT {
+                break;
+            }
+            settled += 1;
+
+            for arc in &out_arcs[node as usize] {
+                let v = arc.node.0;
+                if v == ignore || contracted[v as usize] {
+                    continue;
+                }
+                let distance = key + arc.weight;
+                if distance < self.distances[v as usize] {
+                    if self.distances[v as usize] == f64::INFINITY {
+                        self.touched.push(v);
+                    }
+                    self.distances[v as usize] = distance;
+                    heap.push(State {
+                        key: distance,
+                        node: v,
+                    });
+                }
+            }
+        }
+    }
+
+    fn distance(&self, v: u32) -> f64 {
+        self.distances[v as usize]
+    }
+}
+
+struct Preprocessing {
+    out_arcs: Vec<Vec<Arc>>,
+    in_arcs: Vec<Vec<Arc>>,
+    contracted: Vec<bool>,
+    contracted_neighbors: Vec<i64>,
+    witness_search: WitnessSearch,
+}
+
+impl Preprocessing {
+    // shortcuts (u, x, weight) needed to keep distances when contracting v
+    fn shortcuts(&mut self, v: u32) -> Vec<(u32, u32, f64)> {
+        let mut shortcuts = Vec::new();
+        let max_out = self.out_arcs[v as usize]
+            .iter()
+            .filter(|a| !self.contracted[a.node.0 as usize])
+            .map(|a| a.weight)
+            .fold(0.0, f64::max);
+
+        for i in 0..self.in_arcs[v as usize].len() {
+            let in_arc = self.in_arcs[v as usize][i];
+            let u = in_arc.node.0;
+            if self.contracted[u as usize] {
+                continue;
+            }
+
+            self.witness_search.run(
+                &self.out_arcs,
+                &self.contracted,
+                u,
+                v,
+                in_arc.weight + max_out,
+            );
+
+            for out_arc in &self.out_arcs[v as usize] {
+                let x = out_arc.node.0;
+                if x == u || self.contracted[x as usize] {
+                    continue;
+                }
+                let weight = in_arc.weight + out_arc.weight;
+                if self.witness_search.distance(x) > weight {
+                    shortcuts.push((u, x, weight));
+                }
+            }
+        }
+
+        shortcuts
+    }
+
+    fn degree(&self, v: u32) -> i64 {
+        let contracted = &self.contracted;
+        let count = |arcs: &[Arc]| {
+            arcs.iter()
+                .filter(|a| !contracted[a.node.0 as usize])
+                .count()
+        };
+        (count(&self.out_arcs[v as usize]) + count(&self.in_arcs[v as usize])) as i64
+    }
+
+    // edge difference plus the number of already contracted neighbors, which
+    // spreads the contraction evenly over the graph
+    fn priority(&mut self, v: u32) -> i64 {
+        self.shortcuts(v).len() as i64 - self.degree(v) + self.contracted_neighbors[v as usize]
+    }
+
+    fn contract(&mut self, v: u32) {
+        for (u, x, weight) in self.shortcuts(v) {
+            let shortcut = |node| Arc {
+                node: GraphNodeId(node),
+                weight,
+                via: Via::Node(GraphNodeId(v)),
+            };
+            add_arc(&mut self.out_arcs[u as usize], shortcut(x));
+            add_arc(&mut self.in_arcs[x as usize], shortcut(u));
+        }
+
+        self.contracted[v as usize] = true;
+        for arc in self.out_arcs[v as usize]
+            .iter()
+            .chain(self.in_arcs[v as usize].iter())
+        {
+            self.contracted_neighbors[arc.node.0 as usize] += 1;
+        }
+    }
+}
+
+// adds the arc, or lowers the weight of an existing arc to the same node
+fn add_arc(arcs: &mut Vec<Arc>, arc: Arc) {
+    match arcs.iter_mut().find(|a| a.node == arc.node) {
+        Some(existing) => {
+            if arc.weight < existing.weight {
+                *existing = arc;
+            }
+        }
+        None => arcs.push(arc),
+    }
+}
+
+impl ContractionHierarchy {
+    pub fn new<E: AsRef<EdgeData>>(g: &Graph<NodeData, E>, cost: Cost) -> ContractionHierarchy {
+        let now = Instant::now();
+        let n = g.nodes.len();
+
+        let mut p = Preprocessing {
+            out_arcs: vec![Vec::new(); n],
+            in_arcs: vec![Vec::new(); n],
+            contracted: vec![false; n],
+            contracted_neighbors: vec![0; n],
+            witness_search: WitnessSearch {
+                distances: vec![f64::INFINITY; n],
+                touched: Vec::new(),
+            },
+        };
+
+        for edge in &g.edges {
+            let data = edge.data.as_ref();
+            let weight = edge_cost(data, cost);
+            let mut arcs = vec![(edge.s, edge.t)];
+            if data.bidirectional {
+                arcs.push((edge.t, edge.s));
+            }
+            for (s, t) in arcs {
+                if s == t {
+                    continue;
+                }
+                let arc = |node| Arc {
+                    node,
+                    weight,
+                    via: Via::Edge(edge.id),
+                };
+                add_arc(&mut p.out_arcs[s.0 as usize], arc(t));
+                add_arc(&mut p.in_arcs[t.0 as usize], arc(s));
+            }
+        }
+
+        let mut queue = BinaryHeap::new();
+        for v in 0..n as u32 {
+            queue.push(Reverse((p.priority(v), v)));
+        }
+
+        // lazy updates: a node is only contracted if its recomputed priority
+        // is still the smallest one
+        let mut rank = vec![0; n];
+        let mut next_rank = 0;
+        while let Some(Reverse((_, v))) = queue.pop() {
+            let priority = p.priority(v);
+            if let Some(Reverse((next, _))) = queue.peek() {
+                if priority > *next {
+                    queue.push(Reverse((priority, v)));
+                    continue;
+                }
+            }
+            p.contract(v);
+            rank[v as usize] = next_rank;
+            next_rank += 1;
+        }
+
+        let mut up = vec![Vec::new(); n];
+        let mut down = vec![Vec::new(); n];
+        for v in 0..n {
+            up[v] = p.out_arcs[v]
+                .iter()
+                .filter(|a| rank[a.node.0 as usize] > rank[v])
+                .cloned()
+                .collect();
+            down[v] = p.in_arcs[v]
+                .iter()
+                .filter(|a| rank[a.node.0 as usize] > rank[v])
+                .cloned()
+                .collect();
+        }
+
+        let ch = ContractionHierarchy {
+            cost,
+            node_count: n as u32,
+            edge_count: g.edges.len() as u32,
+            checksum: checksum(g),
+            rank,
+            up,
+            down,
+        };
+
+        info!(
+            "computed contraction hierarchy: {}s",
+            now.elapsed().as_secs()
+        );
+        info!(
+            "#arcs: {} (of which shortcuts: {})",
+            ch.arc_count(),
+            ch.shortcut_count()
+        );
+
+        ch
+    }
+
+    pub fn arc_count(&self) -> usize {
+        self.up
+            .iter()
+            .chain(self.down.iter())
+            .map(|a| a.len())
+            .sum()
+    }
+
+    pub fn shortcut_count(&self) -> usize {
+        self.up
+            .iter()
+            .chain(self.down.iter())
+            .flat_map(|a| a.iter())
+            .filter(|a| match a.via {
+                Via::Node(_) => true,
+                Via::Edge(_) => false,
+            })
+            .count()
+    }
+
+    // fails unless the hierarchy was computed for exactly this graph and cost
+    pub fn check<N, E: AsRef<EdgeData>>(&self, g: &Graph<N, E>, cost: Cost) -> Result<(), Error> {
+        if self.cost != cost {
+            return Err(Error::Data(format!(
+                "contraction hierarchy was computed for cost {:?}, not {:?}",
+                self.cost, cost
+            )));
+        }
+        if self.node_count as usize != g.nodes.len()
+            || self.edge_count as usize != g.edges.len()
+            || self.checksum != checksum(g)
+        {
+            return Err(Error::Data(
+                "contraction hierarchy was computed for a different graph".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn rank(&self, v: GraphNodeId) -> u32 {
+        self.rank[v.0 as usize]
+    }
+
+    // arcs from v to nodes of higher rank
+    pub fn up_arcs(&self, v: GraphNodeId) -> &[Arc] {
+        &self.up[v.0 as usize]
+    }
+
+    // arcs into v from nodes of higher rank
+    pub fn down_arcs(&self, v: GraphNodeId) -> &[Arc] {
+        &self.down[v.0 as usize]
+    }
+
+    // bidirectional upward search; only the cost is computed
+    pub fn distance(&self, s: GraphNodeId, t: GraphNodeId) -> Option<f64> {
+        self.search(s, t).map(|(distance, _, _, _)| distance)
+    }
+
+    pub fn query<E: AsRef<EdgeData>>(
+        &self,
+        g: &Graph<NodeData, E>,
+        s: GraphNodeId,
+        t: GraphNodeId,
+    ) -> Result<Option<Path>, Error> {
+        let (_, meeting, forward, backward) = match self.search(s, t) {
+            Some(search) => search,
+            None => return Ok(None),
+        };
+
+        // (tail, head, via) of the upward path from s and of the downward path to t
+        let mut arcs = Vec::new();
+        let mut v = meeting;
+        while let Some((u, arc)) = forward[v as usize] {
+            arcs.push((u, v, arc.via));
+            v = u;
+        }
+        arcs.reverse();
+        let mut v = meeting;
+        while let Some((x, arc)) = backward[v as usize] {
+            arcs.push((v, x, arc.via));
+            v = x;
+        }
+
+        let mut path = Path {
+            nodes: vec![s],
+            edges: Vec::new(),
+            distance: 0.0,
+            duration: 0.0,
+        };
+        for (tail, head, via) in arcs {
+            self.unpack(tail, head, via, &mut path.edges)?;
+        }
+
+        let mut current = s;
+        for e in &path.edges {
+            let edge = g.edge(*e);
+            path.distance += edge.data.as_ref().length;
+            path.duration += edge.data.as_ref().travel_time();
+            current = edge.other(current);
+            path.nodes.push(current);
+        }
+
+        Ok(Some(path))
+    }
+
+    // costs between all sources and targets, one row per source; the
+    // backward searches from the targets fill a bucket per reached node, the
+    // forward searches from the sources run in parallel and scan the buckets
+    pub fn many_to_many(
+        &self,
+        sources: &[GraphNodeId],
+        targets: &[GraphNodeId],
+    ) -> Vec<Vec<Option<f64>>> {
+        let mut buckets: Vec<Vec<(u32, f64)>> = vec![Vec::new(); self.rank.len()];
+        let searches = util::parallel_map(targets, |t| self.upward_search(*t, &self.down));
+        for (i, search) in searches.into_iter().enumerate() {
+            for (v, distance) in search {
+                buckets[v as usize].push((i as u32, distance));
+            }
+        }
+
+        util::parallel_map(sources, |s| {
+            let mut row = vec![f64::INFINITY; targets.len()];
+            for (v, distance) in self.upward_search(*s, &self.up) {
+                for (i, bucket_distance) in &buckets[v as usize] {
+                    let total = distance + bucket_distance;
+                    if total < row[*i as usize] {
+                        row[*i as usize] = total;
+                    }
+                }
+            }
+            row.into_iter()
+                .map(|d| Some(d).filter(|d| d.is_finite()))
+                .collect()
+        })
+    }
+
+    // all nodes reachable from s over `arcs` with their costs
+    fn upward_search(&self, s: GraphNodeId, arcs: &[Vec<Arc>]) -> Vec<(u32, f64)> {
+        let mut distances: HashMap<u32, f64> = HashMap::new();
+        let mut heap = BinaryHeap::new();
+        let mut settled = Vec::new();
+        distances.insert(s.0, 0.0);
+        heap.push(State {
+            key: 0.0,
+            node: s.0,
+        });
+
+        while let Some(State { key, node }) = heap.pop() {
+            if key > distances[&node] {
+                continue;
+            }
+            settled.push((node, key));
+            for arc in &arcs[node as usize] {
+                let distance = key + arc.weight;
+                let known = distances.entry(arc.node.0).or_insert(f64::INFINITY);
+                if distance < *known {
+                    *known = distance;
+                    heap.push(State {
+                        key: distance,
+                        node: arc.node.0,
+                    });
+                }
+            }
+        }
+
+        settled
+    }
+
+    // returns the cost, the meeting node and the parent arcs of both searches
+    #[allow(clippy::type_complexity)]
+    fn search(
+        &self,
+        s: GraphNodeId,
+        t: GraphNodeId,
+    ) -> Option<(f64, u32, Vec<Option<(u32, Arc)>>, Vec<Option<(u32, Arc)>>)> {
+        let n = self.rank.len();
+        let mut distances = [vec![f64::INFINITY; n], vec![f64::INFINITY; n]];
+        let mut parents: [Vec<Option<(u32, Arc)>>; 2] = [vec![None; n], vec![None; n]];
+        let mut heaps = [BinaryHeap::new(), BinaryHeap::new()];
+
+        for (i, v) in [s, t].iter().enumerate() {
+            distances[i][v.0 as usize] = 0.0;
+            heaps[i].push(State {
+                key: 0.0,
+                node: v.0,
+            });
+        }
+
+        let mut best = f64::INFINITY;
+        let mut meeting = None;
+        loop {
+            let keys: Vec<f64> = heaps
+                .iter()
+                .map(|h| h.peek().map_or(f64::INFINITY, |x| x.key))
+                .collect();
+            if keys[0].min(keys[1]) >= best {
+                break;
+            }
+            let i = if keys[0] <= keys[1] { 0 } else { 1 };
+            let State { key, node } = heaps[i].pop().unwrap();
+            if key > distances[i][node as usize] {
+                continue;
+            }
+
+            let other = distances[1 - i][node as usize];
+            if key + other < best {
+                best = key + other;
+                meeting = Some(node);
+            }
+
+            let arcs = if i == 0 {
+                &self.up[node as usize]
+            } else {
+                &self.down[node as usize]
+            };
+            for arc in arcs {
+                let v = arc.node.0 as usize;
+                let distance = key + arc.weight;
+                if distance < distances[i][v] {
+                    distances[i][v] = distance;
+                    parents[i][v] = Some((node, *arc));
+                    heaps[i].push(State {
+                        key: distance,
+                        node: v as u32,
+                    });
+                }
+            }
+        }
+
+        let [forward, backward] = parents;
+        meeting.map(|m| (best, m, forward, backward))
+    }
+
+    // appends the original edges of the arc tail -> head to `edges`
+    fn unpack(
+        &self,
+        tail: u32,
+        head: u32,
+        via: Via,
+        edges: &mut Vec<GraphEdgeId>,
+    ) -> Result<(), Error> {
+        let mut stack = vec![(tail, head, via)];
+        while let Some((a, b, via)) = stack.pop() {
+            match via {
+                Via::Edge(e) => edges.push(e),
+                Via::Node(m) => {
+                    // pushed in reverse, so that a -> m is unpacked first
+                    stack.push((m.0, b, self.arc(m.0, b)?.via));
+                    stack.push((a, m.0, self.arc(a, m.0)?.via));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // the arc a -> b between two nodes adjacent in the hierarchy
+    fn arc(&self, a: u32, b: u32) -> Result<Arc, Error> {
+        let arc = if self.rank[a as usize] < self.rank[b as usize] {
+            self.up[a as usize].iter().find(|x| x.node.0 == b)
+        } else {
+            self.down[b as usize].iter().find(|x| x.node.0 == a)
+        };
+        arc.cloned().ok_or_else(|| {
+            Error::Data(format!(
+                "contraction hierarchy has a shortcut over missing arc {} -> {}",
+                a, b
+            ))
+        })
+    }
+}
+
+// FNV-1a over end points, direction and weight of every edge, so that a
+// hierarchy is not used with a graph that changed since it was computed
+// only covers values every graph format stores exactly; the text formats
+// round lengths or leave them out, so they can not be part of it
+fn checksum<N, E: AsRef<EdgeData>>(g: &Graph<N, E>) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for edge in &g.edges {
+        let data = edge.data.as_ref();
+        let mut bytes = Vec::with_capacity(10);
+        bytes.extend_from_slice(&edge.s.0.to_le_bytes());
+        bytes.extend_from_slice(&edge.t.0.to_le_bytes());
+        bytes.push(data.bidirectional as u8);
+        bytes.push(data.max_speed);
+        for b in bytes {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+pub fn write(ch: &ContractionHierarchy, filename: String) -> Result<(), io::Error> {
+    let mut f = io::BufWriter::new(File::create(filename)?);
+    write_hierarchy(ch, &mut f)?;
+    f.flush()
+}
+
+pub fn write_hierarchy<W: Write>(ch: &ContractionHierarchy, f: &mut W) -> Result<(), io::Error> {
+    f.write_all(MAGIC)?;
+    write_u32(f, VERSION)?;
+    f.write_all(&[match ch.cost {
+        Cost::Distance => 0,
+        Cost::TravelTime => 1,
+    }])?;
+    write_u32(f, ch.node_count)?;
+    write_u32(f, ch.edge_count)?;
+    f.write_all(&ch.checksum.to_le_bytes())?;
+    for rank in &ch.rank {
+        write_u32(f, *rank)?;
+    }
+
+    for arcs in [&ch.up, &ch.down].iter() {
+        let mut offset = 0;
+        write_u32(f, 0)?;
+        for a in arcs.iter() {
+            offset += a.len();
+            write_u32(f, offset as u32)?;
+        }
+        for arc in arcs.iter().flat_map(|a| a.iter()) {
+            write_u32(f, arc.node.0)?;
+            f.write_all(&arc.weight.to_le_bytes())?;
+            match arc.via {
+                Via::Edge(e) => {
+                    f.write_all(&[0])?;
+                    write_u32(f, e.0)?;
+                }
+                Via::Node(v) => {
+                    f.write_all(&[1])?;
+                    write_u32(f, v.0)?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn read(filename: &str) -> Result<ContractionHierarchy, io::Error> {
+    let mut f = io::BufReader::new(File::open(filename)?);
+    read_hierarchy(&mut f)
+}
+
+pub fn read_hierarchy<R: Read>(f: &mut R) -> Result<ContractionHierarchy, io::Error> {
+    let mut magic = [0u8; 8];
+    f.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("not a contraction hierarchy file".to_string()));
+    }
+    let version = read_u32(f)?;
+    if version != VERSION {
+        return Err(invalid_data(format!(
+            "unsupported contraction hierarchy version: {}",
+            version
+        )));
+    }
+
+    let cost = match read_u8(f)? {
+        0 => Cost::Distance,
+        1 => Cost::TravelTime,
+        x => return Err(invalid_data(format!("invalid cost: {}", x))),
+    };
+    let node_count = read_u32(f)?;
+    let edge_count = read_u32(f)?;
+    let checksum = read_u64(f)?;
+    let n = node_count as usize;
+    let rank = read_array(f, n, read_u32)?;
+
+    let mut arcs = Vec::new();
+    for _ in 0..2 {
+        let offsets = read_array(f, n + 1, read_u32)?;
+        let mut node_arcs = Vec::with_capacity(n);
+        for v in 0..n {
+            if offsets[v] > offsets[v + 1] {
+                return Err(invalid_data(format!("invalid arc offsets at node {}", v)));
+            }
+            let mut a = Vec::new();
+            for _ in offsets[v]..offsets[v + 1] {
+                let node = read_u32(f)?;
+                let weight = read_f64(f)?;
+                let kind = read_u8(f)?;
+                let via = read_u32(f)?;
+                if node >= node_count {
+                    return Err(invalid_data(format!("invalid arc target: {}", node)));
+                }
+                let via = match kind {
+                    0 if via < edge_count => Via::Edge(GraphEdgeId(via)),
+                    1 if via < node_count => Via::Node(GraphNodeId(via)),
+                    _ => return Err(invalid_data(format!("invalid arc at node {}", v))),
+                };
+                a.push(Arc {
+                    node: GraphNodeId(node),
+                    weight,
+                    via,
+                });
+            }
+            node_arcs.push(a);
+        }
+        arcs.push(node_arcs);
+    }
+    let down = arcs.pop().unwrap();
+    let up = arcs.pop().unwrap();
+
+    Ok(ContractionHierarchy {
+        cost,
+        node_count,
+        edge_count,
+        checksum,
+        rank,
+        up,
+        down,
+    })
+}
+
+// TESTS
+#[cfg(test)]
+use osmpbfreader::{NodeId, WayId};
+#[cfg(test)]
+use routing;
+
+// a size x size grid with some oneway streets, varying speeds and a few
+// missing edges, generated from a fixed seed
+#[cfg(test)]
+fn create_graph(size: u32) -> Graph<NodeData, EdgeData> {
+    let mut g = Graph {
+        nodes: Vec::new(),
+        edges: Vec::new(),
+    };
+    for i in 0..size * size {
+        g.add_node(NodeData {
+            osm_id: NodeId(i as i64),
+            lat: 52.0 + 0.001 * (i / size) as f64,
+            lon: 13.0 + 0.001 * (i % size) as f64,
+        });
+    }
+
+    let mut seed: u64 = 42;
+    let mut random = move || {
+        seed = seed
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        (seed >> 33) as u32
+    };
+
+    for i in 0..size * size {
+        let neighbors = [
+            (i % size + 1 < size, i + 1),
+            (i / size + 1 < size, i + size),
+        ];
+        for (exists, j) in neighbors.iter() {
+            if !exists || random() % 10 == 0 {
+                continue;
+            }
+            let (s, t) = if random() % 2 == 0 { (i, *j) } else { (*j, i) };
+            let a = (g.nodes[s as usize].data.lat, g.nodes[s as usize].data.lon);
+            let b = (g.nodes[t as usize].data.lat, g.nodes[t as usize].data.lon);
+            let bidirectional = random() % 4 != 0;
+            let data = EdgeData {
+                osm_way_id: WayId(i as i64),
+                osm_way_piece: 0,
+                name: String::new(),
+                length: util::haversine_distance(a, b) * (1.0 + (random() % 5) as f64 / 10.0),
+                street_type: "residential".to_string(),
+                max_speed: [10, 30, 50, 100][(random() % 4) as usize],
+                bidirectional,
+                geometry: vec![a, b],
+            };
+            g.add_edge(GraphNodeId(s), GraphNodeId(t), data, bidirectional);
+        }
+    }
+    g
+}
+
+#[test]
+fn should_match_dijkstra() {
+    let g = create_graph(12);
+    let n = g.nodes.len() as u32;
+
+    for cost in [Cost::Distance, Cost::TravelTime].iter() {
+        let ch = ContractionHierarchy::new(&g, *cost);
+        assert!(ch.check(&g, *cost).is_ok());
+        assert!(ch.shortcut_count() > 0);
+
+        for s in (0..n).step_by(7) {
+            for t in (0..n).step_by(5) {
+                let (s, t) = (GraphNodeId(s), GraphNodeId(t));
+                let expected = routing::dijkstra(&g, s, t, *cost);
+                let path = ch.query(&g, s, t).unwrap();
+                let distance = ch.distance(s, t);
+
+                match (expected, path, distance) {
+                    (None, None, None) => {}
+                    (Some(expected), Some(path), Some(distance)) => {
+                        let expected_cost: f64 = expected
+                            .edges
+                            .iter()
+                            .map(|e| edge_cost(&g.edge(*e).data, *cost))
+                            .sum();
+                        let path_cost: f64 = path
+                            .edges
+                            .iter()
+                            .map(|e| edge_cost(&g.edge(*e).data, *cost))
+                            .sum();
+                        assert!((expected_cost - path_cost).abs() < 1e-6);
+                        assert!((expected_cost - distance).abs() < 1e-6);
+                        assert!(path.nodes[0] == s && *path.nodes.last().unwrap() == t);
+                        // every edge is traversed in an allowed direction
+                        for (i, e) in path.edges.iter().enumerate() {
+                            let edge = g.edge(*e);
+                            assert!(edge.s == path.nodes[i] || edge.data.bidirectional);
+                        }
+                    }
+                    _ => panic!("reachability differs from dijkstra"),
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn should_compute_many_to_many_costs() {
+    let g = create_graph(10);
+    let ch = ContractionHierarchy::new(&g, Cost::TravelTime);
+    let sources: Vec<GraphNodeId> = (0..100).step_by(9).map(GraphNodeId).collect();
+    let targets: Vec<GraphNodeId> = (0..100).step_by(13).map(GraphNodeId).collect();
+
+    let table = ch.many_to_many(&sources, &targets);
+
+    assert!(table.len() == sources.len());
+    for (s, row) in sources.iter().zip(table.iter()) {
+        let expected = routing::one_to_many(&g, *s, &targets, Cost::TravelTime);
+        assert!(row.len() == targets.len());
+        for (a, b) in row.iter().zip(expected.iter()) {
+            match (a, b) {
+                (Some(a), Some(b)) => assert!((a - b).abs() < 1e-6),
+                (None, None) => {}
+                _ => panic!("reachability differs from dijkstra"),
+            }
+        }
+    }
+}
+
+#[test]
+fn should_round_trip_hierarchy() {
+    let g = create_graph(6);
+    let ch = ContractionHierarchy::new(&g, Cost::TravelTime);
+
+    let mut buffer = Vec::new();
+    write_hierarchy(&ch, &mut buffer).unwrap();
+    let read = read_hierarchy(&mut buffer.as_slice()).unwrap();
+
+    assert!(read.cost == Cost::TravelTime);
+    assert!(read.check(&g, Cost::TravelTime).is_ok());
+    assert!(read.rank == ch.rank);
+    assert!(read.up == ch.up);
+    assert!(read.down == ch.down);
+
+    assert!(read.check(&g, Cost::Distance).is_err());
+    let mut changed = create_graph(6);
+    changed.edges[3].data.max_speed += 10;
+    match read.check(&changed, Cost::TravelTime) {
+        Err(Error::Data(_)) => {}
+        _ => panic!("hierarchy accepted for a changed graph"),
+    }
+
+    let mut not_a_hierarchy = b"ROADGRPH".to_vec();
+    not_a_hierarchy.extend_from_slice(&VERSION.to_le_bytes());
+    assert!(read_hierarchy(&mut not_a_hierarchy.as_slice()).is_err());
+}
+
+#[test]
+fn should_report_shortcuts_over_missing_arcs() {
+    // shortcut 0 -> 2 over node 1, whose arcs are missing
+    let ch = ContractionHierarchy {
+        cost: Cost::Distance,
+        node_count: 3,
+        edge_count: 0,
+        checksum: 0,
+        rank: vec![1, 0, 2],
+        up: vec![
+            vec![Arc {
+                node: GraphNodeId(2),
+                weight: 2.0,
+                via: Via::Node(GraphNodeId(1)),
+            }],
+            Vec::new(),
+            Vec::new(),
+        ],
+        down: vec![Vec::new(); 3],
+    };
+
+    let g = create_graph(2);
+    match ch.query(&g, GraphNodeId(0), GraphNodeId(2)) {
+        Err(Error::Data(message)) => assert!(message.ends_with("missing arc 1 -> 2")),
+        _ => panic!("missing arc not reported"),
+    }
+}
+
+#[test]
+fn should_route_with_hierarchy_of_converted_text_graph() {
+    use connected_components::{reduce_to_largest_component, ComponentType};
+    use network_type::NetworkType;
+    use osm_convert;
+    use osm_parse_config_creator;
+    use osm_reader;
+    use output;
+
+    let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/grid.osm");
+    let config = osm_parse_config_creator::create_default_config().unwrap();
+    let (nodes, ways, _) = osm_reader::read_osm(fixture, &config, &NetworkType::Car, None).unwrap();
+    let g = osm_convert::convert(nodes, ways, &config, &NetworkType::Car).unwrap();
+    let g = reduce_to_largest_component(g, ComponentType::Strong);
+    let n = g.nodes.len() as u32;
+    assert!(n > 2);
+
+    for version in [
+        output::FormatVersion::Legacy,
+        output::FormatVersion::Corrected,
+    ]
+    .iter()
+    {
+        for cost in [Cost::Distance, Cost::TravelTime].iter() {
+            let filename = std::env::temp_dir().join(format!(
+                "osmtoroadgraph_ch_text_test_{:?}_{:?}.pycgr",
+                version, cost
+            ));
+            let filename = filename.to_str().unwrap().to_string();
+            output::write(&g, filename.clone(), *version).unwrap();
+            output::write_names(&g, format!("{}_names", filename)).unwrap();
+            write(
+                &ContractionHierarchy::new(&g, *cost),
+                format!("{}_ch", filename),
+            )
+            .unwrap();
+
+            let read_graph = output::read(&filename).unwrap();
+            let ch = read(&format!("{}_ch", filename)).unwrap();
+            for suffix in ["", "_names", "_ch"].iter() {
+                std::fs::remove_file(format!("{}{}", filename, suffix)).unwrap();
+            }
+
+            assert!(ch.check(&read_graph, *cost).is_ok());
+            for s in 0..n {
+                for t in 0..n {
+                    let (s, t) = (GraphNodeId(s), GraphNodeId(t));
+                    let path = ch.query(&read_graph, s, t).unwrap().unwrap();
+                    let expected = routing::dijkstra(&read_graph, s, t, *cost).unwrap();
+                    assert!(path.nodes[0] == s && *path.nodes.last().unwrap() == t);
+                    assert!(path.edges.len() == expected.edges.len());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use osmpbfreader;
+use quick_xml;
+use std::error;
+use std::fmt;
+use std::io;
+
+// everything that can go wrong between parsing the arguments and writing the output
+#[derive(Debug)]
+pub enum Error {
+    // a file could not be opened, read or written
+    Io(io::Error),
+    // the PBF input could not be decoded
+    Pbf(osmpbfreader::Error),
+    // the OSM XML input could not be parsed
+    Xml(String),
+    // the parse config does not have the expected layout
+    Config(String),
+    // the input contradicts itself, e.g. a way refers to a node that is missing
+    // or a graph file does not match its counts
+    Data(String),
+    // a command line value could not be understood
+    Argument(String),
+}
+
+impl Error {
+    // exit code of the command line tool; panics exit with 101
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            Error::Argument(_) => 2,
+            Error::Io(_) => 3,
+            Error::Pbf(_) => 4,
+            Error::Xml(_) => 5,
+            Error::Config(_) => 6,
+            Error::Data(_) => 7,
+        }
+    }
+
+    // names the file the error happened in
+    pub fn in_file(self, filename: &str) -> Error {
+        match self {
+            Error::Io(e) => Error::Io(io::Error::new(e.kind(), format!("{}: {}", filename, e))),
+            Error::Xml(e) => Error::Xml(format!("{}: {}", filename, e)),
+            Error::Config(e) => Error::Config(format!("{}: {}", filename, e)),
+            Error::Data(e) => Error::Data(format!("{}: {}", filename, e)),
+            error => error,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "{}", e),
+            Error::Pbf(ref e) => write!(f, "could not decode PBF input: {}", e),
+            Error::Xml(ref e) => write!(f, "could not parse OSM XML input: {}", e),
+            Error::Config(ref e) => write!(f, "invalid config: {}", e),
+            Error::Data(ref e) => write!(f, "inconsistent data: {}", e),
+            Error::Argument(ref e) => write!(f, "invalid argument: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            Error::Pbf(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+// the file readers report malformed content as InvalidData, and truncated
+// files end unexpectedly
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        match err.kind() {
+            io::ErrorKind::InvalidData => Error::Data(err.to_string()),
+            io::ErrorKind::UnexpectedEof => Error::Data("unexpected end of file".to_string()),
+            _ => Error::Io(err),
+        }
+    }
+}
+
+impl From<osmpbfreader::Error> for Error {
+    fn from(err: osmpbfreader::Error) -> Error {
+        match err {
+            osmpbfreader::Error::Io(err) => Error::Io(err),
+            err => Error::Pbf(err),
+        }
+    }
+}
+
+impl From<quick_xml::Error> for Error {
+    fn from(err: quick_xml::Error) -> Error {
+        match err {
+            quick_xml::Error::Io(err) => Error::Io(io::Error::new(err.kind(), err.to_string())),
+            err => Error::Xml(err.to_string()),
+        }
+    }
+}
+
+impl From<quick_xml::events::attributes::AttrError> for Error {
+    fn from(err: quick_xml::events::attributes::AttrError) -> Error {
+        Error::Xml(err.to_string())
+    }
+}
+
+// TESTS
+#[test]
+fn should_map_and_locate_errors() {
+    let error: Error = io::Error::new(io::ErrorKind::InvalidData, "line 2: invalid").into();
+    assert!(error.exit_code() == 7);
+    assert!(error.to_string() == "inconsistent data: line 2: invalid");
+
+    let error: Error = io::Error::new(io::ErrorKind::NotFound, "missing.pbf").into();
+    assert!(error.exit_code() == 3);
+    assert!(error.to_string() == "missing.pbf");
+
+    let error = Error::from(io::Error::new(io::ErrorKind::NotFound, "not found")).in_file("a.osm");
+    assert!(error.to_string() == "a.osm: not found");
+    let error = Error::Data("line 2: invalid".to_string()).in_file("a.pycgr");
+    assert!(error.to_string() == "inconsistent data: a.pycgr: line 2: invalid");
+}
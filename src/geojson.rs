@@ -0,0 +1,157 @@
+use graph::Graph;
+use graph_data::{EdgeData, NodeData};
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+
+// writes nodes as Point and edges as LineString features into a single FeatureCollection
+pub fn write<E: AsRef<EdgeData>>(
+    g: &Graph<NodeData, E>,
+    filename: String,
+) -> Result<(), io::Error> {
+    let mut f = io::BufWriter::new(File::create(filename)?);
+    write_graph(g, &mut f)?;
+    f.flush()
+}
+
+pub fn write_graph<E: AsRef<EdgeData>, W: Write>(
+    g: &Graph<NodeData, E>,
+    f: &mut W,
+) -> Result<(), io::Error> {
+    f.write_all(b"{\"type\":\"FeatureCollection\",\"features\":[\n")?;
+
+    let mut first = true;
+    for node in &g.nodes {
+        if !first {
+            f.write_all(b",\n")?;
+        }
+        first = false;
+
+        write!(
+            f,
+            "{{\"type\":\"Feature\",\"geometry\":{{\"type\":\"Point\",\"coordinates\":{}}},\
+             \"properties\":{{\"id\":{},\"osm_id\":{}}}}}",
+            coordinate(node.data.lat, node.data.lon),
+            node.id.0,
+            node.data.osm_id.0
+        )?;
+    }
+
+    for edge in &g.edges {
+        if !first {
+            f.write_all(b",\n")?;
+        }
+        first = false;
+
+        let data = edge.data.as_ref();
+        let coordinates = data
+            .geometry
+            .iter()
+            .map(|(lat, lon)| coordinate(*lat, *lon))
+            .collect::<Vec<String>>()
+            .join(",");
+
+        write!(
+            f,
+            "{{\"type\":\"Feature\",\"geometry\":{{\"type\":\"LineString\",\"coordinates\":[{}]}},\
+             \"properties\":{{\"id\":{},\"source\":{},\"target\":{},\"osm_way_id\":{},\
+             \"osm_way_piece\":{},\"name\":{},\"length\":{:.3},\"street_type\":{},\"max_speed\":{},\
+             \"bidirectional\":{}}}}}",
+            coordinates,
+            edge.id.0,
+            edge.s.0,
+            edge.t.0,
+            data.osm_way_id.0,
+            data.osm_way_piece,
+            escape(&data.name),
+            data.length,
+            escape(&data.street_type),
+            data.max_speed,
+            data.bidirectional
+        )?;
+    }
+
+    f.write_all(b"\n]}\n")?;
+
+    Ok(())
+}
+
+// GeoJSON positions are [lon, lat]
+pub fn coordinate(lat: f64, lon: f64) -> String {
+    format!("[{:.7},{:.7}]", lon, lat)
+}
+
+fn escape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len() + 2);
+    result.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+// TESTS
+#[cfg(test)]
+use graph::GraphNodeId;
+#[cfg(test)]
+use osmpbfreader::{NodeId, WayId};
+
+#[test]
+fn should_escape_strings() {
+    assert!(escape("Main Street") == "\"Main Street\"");
+    assert!(escape("a \"b\" \\ c\n") == "\"a \\\"b\\\" \\\\ c\\n\"");
+    assert!(escape("\u{1}") == "\"\\u0001\"");
+}
+
+#[test]
+fn should_write_points_and_linestrings() {
+    let mut g: Graph<NodeData, EdgeData> = Graph {
+        nodes: Vec::new(),
+        edges: Vec::new(),
+    };
+    for (i, lat) in [52.5, 52.6].iter().enumerate() {
+        g.add_node(NodeData {
+            osm_id: NodeId(10 + i as i64),
+            lat: *lat,
+            lon: 13.4,
+        });
+    }
+    let data = EdgeData {
+        osm_way_id: WayId(7),
+        osm_way_piece: 1,
+        name: "Straße \"A\"".to_string(),
+        length: 11119.5,
+        street_type: "primary".to_string(),
+        max_speed: 50,
+        bidirectional: false,
+        geometry: vec![(52.5, 13.4), (52.55, 13.45), (52.6, 13.4)],
+    };
+    g.add_edge(GraphNodeId(0), GraphNodeId(1), data, false);
+
+    let mut output = Vec::new();
+    write_graph(&g, &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+
+    assert!(output.starts_with("{\"type\":\"FeatureCollection\""));
+    assert!(output.contains(
+        "\"geometry\":{\"type\":\"Point\",\"coordinates\":[13.4000000,52.5000000]},\
+         \"properties\":{\"id\":0,\"osm_id\":10}"
+    ));
+    assert!(output.contains(
+        "\"coordinates\":[[13.4000000,52.5000000],[13.4500000,52.5500000],[13.4000000,52.6000000]]"
+    ));
+    assert!(output.contains("\"name\":\"Straße \\\"A\\\"\""));
+    assert!(output.contains("\"osm_way_id\":7,\"osm_way_piece\":1"));
+    assert!(output.contains("\"bidirectional\":false"));
+    assert!(output.matches("\"type\":\"Feature\"").count() == 3);
+}
@@ -1,9 +1,9 @@
 use graph_data::EdgeDataDescription;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct GraphNodeId(pub u32);
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct GraphEdgeId(pub u32);
 
 pub struct GraphNode<T> {
@@ -38,6 +38,17 @@ impl<T> GraphNode<T> {
     }
 }
 
+impl<T> GraphEdge<T> {
+    // the end point of this edge opposite to `id`
+    pub fn other(&self, id: GraphNodeId) -> GraphNodeId {
+        if self.s == id {
+            self.t
+        } else {
+            self.s
+        }
+    }
+}
+
 impl<T: EdgeDataDescription> GraphEdgeDescription for GraphEdge<T> {
     fn description(&self) -> String {
         format!("{} {} {}", self.s.0, self.t.0, self.data.description())
@@ -85,4 +96,56 @@ impl<NodeData, EdgeData> Graph<NodeData, EdgeData> {
                 .add_edge(edge_index);
         }
     }
+
+    pub fn edge(&self, id: GraphEdgeId) -> &GraphEdge<EdgeData> {
+        &self.edges[id.0 as usize]
+    }
+
+    // nodes reachable from `id` by traversing one of its out edges
+    pub fn out_neighbors(&self, id: GraphNodeId) -> impl Iterator<Item = GraphNodeId> + '_ {
+        self.nodes[id.0 as usize]
+            .get_edges()
+            .iter()
+            .map(move |e| self.edge(*e).other(id))
+    }
+
+    // keeps only the nodes flagged in `keep` and the edges between them;
+    // node and edge ids are renumbered densely in their original order
+    pub fn retain_nodes(self, keep: &[bool]) -> Graph<NodeData, EdgeData> {
+        // an edge is bidirectional iff it is also registered as out edge of its target
+        let mut seen_at_source = vec![false; self.edges.len()];
+        let mut is_bidirectional = vec![false; self.edges.len()];
+        for node in &self.nodes {
+            for e in node.get_edges() {
+                let i = e.0 as usize;
+                if self.edges[i].s == node.id && !seen_at_source[i] {
+                    seen_at_source[i] = true;
+                } else {
+                    is_bidirectional[i] = true;
+                }
+            }
+        }
+
+        let mut new_node_ids = vec![None; self.nodes.len()];
+        let mut g = Graph {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+        };
+
+        for node in self.nodes {
+            if keep[node.id.0 as usize] {
+                new_node_ids[node.id.0 as usize] = Some(g.add_node(node.data));
+            }
+        }
+
+        for edge in self.edges {
+            let s = new_node_ids[edge.s.0 as usize];
+            let t = new_node_ids[edge.t.0 as usize];
+            if let (Some(s), Some(t)) = (s, t) {
+                g.add_edge(s, t, edge.data, is_bidirectional[edge.id.0 as usize]);
+            }
+        }
+
+        g
+    }
 }
@@ -1,17 +1,46 @@
+use osmpbfreader::{NodeId, WayId};
+
 pub struct NodeData {
+    pub osm_id: NodeId,
     pub lat: f64,
     pub lon: f64,
 }
 
 pub struct EdgeData {
+    // the OSM way this edge is a part of
+    pub osm_way_id: WayId,
+    // the part of the way the edge lies on, counted from 0; a way only has
+    // several parts if it was cut at the boundary of the clip region
+    pub osm_way_piece: u32,
     pub name: String,
+    // length in meters along the full geometry
+    pub length: f64,
     pub street_type: String,
     pub max_speed: u8,
     pub bidirectional: bool,
+    // (lat, lon) of every OSM node along the edge, including both end points
+    pub geometry: Vec<(f64, f64)>,
+}
+
+impl EdgeData {
+    // seconds needed to traverse the edge at max_speed
+    pub fn travel_time(&self) -> f64 {
+        self.length / (self.max_speed.max(1) as f64 / 3.6)
+    }
 }
 
 pub trait NodeDataDescription {
+    fn osm_id(&self) -> Option<i64> {
+        None
+    }
+
     fn description(&self) -> String;
+
+    // the columns lat, lon of the CSV output, left empty for data without
+    // coordinates
+    fn csv_fields(&self) -> Vec<String> {
+        vec![String::new(); 2]
+    }
 }
 
 pub trait EdgeDataDescription {
@@ -19,13 +48,45 @@ pub trait EdgeDataDescription {
         "".to_string()
     }
 
+    // id of the OSM way the edge originates from
+    fn osm_id(&self) -> Option<i64> {
+        None
+    }
+
+    // the columns street_type, max_speed, bidirectional as written since v.0.4
     fn description(&self) -> String;
+
+    // length in meters and travel time in seconds, appended to the edge lines
+    // from v.0.5 on
+    fn length_and_travel_time(&self) -> Option<(f64, f64)> {
+        None
+    }
+
+    // the columns length, street_type, max_speed, bidirectional of the CSV
+    // output, left empty for data without them
+    fn csv_fields(&self) -> Vec<String> {
+        vec![String::new(); 4]
+    }
 }
 
 impl NodeDataDescription for NodeData {
+    fn osm_id(&self) -> Option<i64> {
+        Some(self.osm_id.0)
+    }
+
     fn description(&self) -> String {
         format!("{:.6} {:.6}", self.lat, self.lon)
     }
+
+    fn csv_fields(&self) -> Vec<String> {
+        vec![format!("{:.7}", self.lat), format!("{:.7}", self.lon)]
+    }
+}
+
+impl AsRef<EdgeData> for EdgeData {
+    fn as_ref(&self) -> &EdgeData {
+        self
+    }
 }
 
 impl EdgeDataDescription for EdgeData {
@@ -33,8 +94,25 @@ impl EdgeDataDescription for EdgeData {
         self.name.to_owned()
     }
 
+    fn osm_id(&self) -> Option<i64> {
+        Some(self.osm_way_id.0)
+    }
+
     fn description(&self) -> String {
         let dir = if self.bidirectional { 1 } else { 0 };
         format!("{} {} {}", self.street_type, self.max_speed, dir)
     }
+
+    fn length_and_travel_time(&self) -> Option<(f64, f64)> {
+        Some((self.length, self.travel_time()))
+    }
+
+    fn csv_fields(&self) -> Vec<String> {
+        vec![
+            format!("{:.3}", self.length),
+            self.street_type.to_owned(),
+            self.max_speed.to_string(),
+            self.bidirectional.to_string(),
+        ]
+    }
 }
@@ -0,0 +1,767 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::time::Instant;
+
+use clipping::ring_contains;
+use error::Error;
+use geojson::coordinate;
+use graph::{Graph, GraphEdgeId, GraphNodeId};
+use graph_data::{EdgeData, NodeData};
+use routing::{costs_within, edge_cost, Cost};
+use spatial_index::edge_geometry;
+use util::{haversine_distance, METERS_PER_DEGREE};
+
+// largest raster a hull is computed on, about 32MB of cells
+const MAX_HULL_CELLS: i64 = 32_000_000;
+
+// a reachable part of an edge, as fractions of its length measured from edge.s
+#[derive(PartialEq, Debug)]
+pub struct EdgePart {
+    pub edge: GraphEdgeId,
+    pub start: f64,
+    pub end: f64,
+}
+
+pub struct Isochrone {
+    pub threshold: f64,
+    // reachable nodes and the cost to reach them
+    pub nodes: Vec<(GraphNodeId, f64)>,
+    pub edges: Vec<EdgePart>,
+    // polygons of (lat, lon) rings, the first ring is the outline and the others are holes
+    pub hull: Vec<Vec<Vec<(f64, f64)>>>,
+}
+
+// parses "300,600,900" into ascending thresholds
+pub fn parse_thresholds(thresholds: &str) -> Result<Vec<f64>, Error> {
+    let mut values = thresholds
+        .split(',')
+        .map(|x| match x.trim().parse::<f64>() {
+            Ok(value) if value.is_finite() && value > 0.0 => Ok(value),
+            _ => Err(Error::Argument(format!("invalid threshold: {}", x))),
+        })
+        .collect::<Result<Vec<f64>, Error>>()?;
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    values.dedup();
+    Ok(values)
+}
+
+// one isochrone per threshold around s, all from a single bounded Dijkstra
+pub fn compute<E: AsRef<EdgeData>>(
+    g: &Graph<NodeData, E>,
+    s: GraphNodeId,
+    thresholds: &[f64],
+    cost: Cost,
+    hull_radius: f64,
+) -> Result<Vec<Isochrone>, Error> {
+    let now = Instant::now();
+
+    let budget = thresholds.iter().cloned().fold(0.0, f64::max);
+    let costs = costs_within(g, s, cost, budget);
+    let origin = (
+        g.nodes[s.0 as usize].data.lat,
+        g.nodes[s.0 as usize].data.lon,
+    );
+
+    let isochrones = thresholds
+        .iter()
+        .map(|threshold| {
+            let nodes: Vec<(GraphNodeId, f64)> = g
+                .nodes
+                .iter()
+                .filter(|node| costs[node.id.0 as usize] <= *threshold)
+                .map(|node| (node.id, costs[node.id.0 as usize]))
+                .collect();
+            let edges = reachable_edges(g, &costs, cost, *threshold);
+            let hull = hull(g, origin, &nodes, &edges, hull_radius)?;
+            Ok(Isochrone {
+                threshold: *threshold,
+                nodes,
+                edges,
+                hull,
+            })
+        })
+        .collect::<Result<Vec<Isochrone>, Error>>()?;
+
+    info!(
+        "computed {} isochrones: {}s",
+        isochrones.len(),
+        now.elapsed().as_secs()
+    );
+
+    Ok(isochrones)
+}
+
+// edges are entered from s, and from t if they are bidirectional; a part
+// ends where the remaining budget runs out
+fn reachable_edges<E: AsRef<EdgeData>>(
+    g: &Graph<NodeData, E>,
+    costs: &[f64],
+    cost: Cost,
+    threshold: f64,
+) -> Vec<EdgePart> {
+    let mut parts = Vec::new();
+    for edge in &g.edges {
+        let data = edge.data.as_ref();
+        let weight = edge_cost(data, cost);
+        let reach = |node: GraphNodeId| {
+            let remaining = threshold - costs[node.0 as usize];
+            if remaining < 0.0 {
+                None
+            } else if weight > 0.0 {
+                Some((remaining / weight).min(1.0))
+            } else {
+                Some(1.0)
+            }
+        };
+
+        let from_s = reach(edge.s);
+        let from_t = if data.bidirectional {
+            reach(edge.t)
+        } else {
+            None
+        };
+
+        match (from_s, from_t) {
+            (Some(a), Some(b)) if a + b >= 1.0 => parts.push(EdgePart {
+                edge: edge.id,
+                start: 0.0,
+                end: 1.0,
+            }),
+            (a, b) => {
+                if let Some(a) = a.filter(|a| *a > 0.0) {
+                    parts.push(EdgePart {
+                        edge: edge.id,
+                        start: 0.0,
+                        end: a,
+                    });
+                }
+                if let Some(b) = b.filter(|b| *b > 0.0) {
+                    parts.push(EdgePart {
+                        edge: edge.id,
+                        start: 1.0 - b,
+                        end: 1.0,
+                    });
+                }
+            }
+        }
+    }
+    parts
+}
+
+// the part of a (lat, lon) polyline between two fractions of its length
+pub fn cut_geometry(geometry: &[(f64, f64)], start: f64, end: f64) -> Vec<(f64, f64)> {
+    let lengths: Vec<f64> = geometry
+        .windows(2)
+        .map(|w| haversine_distance(w[0], w[1]))
+        .collect();
+    let total: f64 = lengths.iter().sum();
+    let (from, to) = (start * total, end * total);
+
+    let mut result = Vec::new();
+    let mut position = 0.0;
+    for (i, length) in lengths.iter().enumerate() {
+        let next = position + length;
+        let point = |at: f64| {
+            let t = if *length > 0.0 {
+                ((at - position) / length).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let (a, b) = (geometry[i], geometry[i + 1]);
+            (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t)
+        };
+
+        if result.is_empty() && from <= next {
+            result.push(point(from));
+        }
+        if !result.is_empty() {
+            if to <= next {
+                result.push(point(to));
+                break;
+            }
+            result.push(geometry[i + 1]);
+        }
+        position = next;
+    }
+    result
+}
+
+// concave hull: the reachable network is rasterized with cells of half the
+// radius, every cell within the radius of it is filled and the outlines of
+// the filled cells are traced into polygons with holes; the staircase
+// outlines are simplified to a tolerance of one cell
+#[allow(clippy::type_complexity)]
+fn hull<E: AsRef<EdgeData>>(
+    g: &Graph<NodeData, E>,
+    origin: (f64, f64),
+    nodes: &[(GraphNodeId, f64)],
+    edges: &[EdgePart],
+    radius: f64,
+) -> Result<Vec<Vec<Vec<(f64, f64)>>>, Error> {
+    let cell = radius / 2.0;
+    let cos_lat = origin.0.to_radians().cos();
+    let project = |(lat, lon): (f64, f64)| {
+        (
+            (lon - origin.1) * METERS_PER_DEGREE * cos_lat,
+            (lat - origin.0) * METERS_PER_DEGREE,
+        )
+    };
+
+    let mut points: Vec<(f64, f64)> = nodes
+        .iter()
+        .map(|(node, _)| {
+            let data = &g.nodes[node.0 as usize].data;
+            project((data.lat, data.lon))
+        })
+        .collect();
+    let lines: Vec<Vec<(f64, f64)>> = edges
+        .iter()
+        .map(|part| {
+            cut_geometry(&edge_geometry(g, part.edge), part.start, part.end)
+                .into_iter()
+                .map(project)
+                .collect()
+        })
+        .collect();
+    let all_points = || points.iter().chain(lines.iter().flat_map(|l| l.iter()));
+    if all_points().next().is_none() {
+        return Ok(Vec::new());
+    }
+
+    // one empty cell of margin keeps all outlines inside the grid
+    let margin = radius + cell;
+    let min_x = all_points().map(|p| p.0).fold(f64::INFINITY, f64::min) - margin;
+    let min_y = all_points().map(|p| p.1).fold(f64::INFINITY, f64::min) - margin;
+    let max_x = all_points().map(|p| p.0).fold(f64::NEG_INFINITY, f64::max) + margin;
+    let max_y = all_points().map(|p| p.1).fold(f64::NEG_INFINITY, f64::max) + margin;
+    let width = ((max_x - min_x) / cell).ceil() as i64;
+    let height = ((max_y - min_y) / cell).ceil() as i64;
+    if width.saturating_mul(height) > MAX_HULL_CELLS {
+        return Err(Error::Argument(format!(
+            "hull radius of {}m is too small for an isochrone of {:.0}m x {:.0}m, \
+             use at least {:.0}m",
+            radius,
+            max_x - min_x,
+            max_y - min_y,
+            2.0 * ((max_x - min_x) * (max_y - min_y) / MAX_HULL_CELLS as f64).sqrt()
+        )));
+    }
+
+    // sample the edge parts at least once per cell
+    for line in &lines {
+        for w in line.windows(2) {
+            let (a, b) = (w[0], w[1]);
+            let steps = ((b.0 - a.0).hypot(b.1 - a.1) / cell).ceil().max(1.0) as usize;
+            for k in 0..=steps {
+                let t = k as f64 / steps as f64;
+                points.push((a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t));
+            }
+        }
+    }
+
+    let mut filled = vec![false; (width * height) as usize];
+    let reach = (radius / cell).ceil() as i64;
+    for (x, y) in &points {
+        let (ci, cj) = (
+            ((x - min_x) / cell).floor() as i64,
+            ((y - min_y) / cell).floor() as i64,
+        );
+        for i in (ci - reach).max(0)..=(ci + reach).min(width - 1) {
+            for j in (cj - reach).max(0)..=(cj + reach).min(height - 1) {
+                let center_x = min_x + (i as f64 + 0.5) * cell;
+                let center_y = min_y + (j as f64 + 0.5) * cell;
+                if (center_x - x).hypot(center_y - y) <= radius {
+                    filled[(j * width + i) as usize] = true;
+                }
+            }
+        }
+    }
+
+    let is_filled = |i: i64, j: i64| {
+        i >= 0 && j >= 0 && i < width && j < height && filled[(j * width + i) as usize]
+    };
+    let rings = trace_rings(width, height, &is_filled);
+
+    // outer rings run counterclockwise and holes clockwise
+    let mut outers: Vec<(Vec<(i64, i64)>, f64)> = Vec::new();
+    let mut holes: Vec<Vec<(i64, i64)>> = Vec::new();
+    for ring in rings {
+        let area = signed_area(&ring);
+        if area > 0.0 {
+            outers.push((ring, area));
+        } else {
+            holes.push(ring);
+        }
+    }
+
+    let as_f64 = |ring: &[(i64, i64)]| -> Vec<(f64, f64)> {
+        ring.iter().map(|(i, j)| (*i as f64, *j as f64)).collect()
+    };
+    let outer_rings: Vec<Vec<(f64, f64)>> = outers.iter().map(|(ring, _)| as_f64(ring)).collect();
+    let mut polygons: Vec<Vec<Vec<(i64, i64)>>> =
+        outers.iter().map(|(ring, _)| vec![ring.clone()]).collect();
+    for hole in holes {
+        // the filled cell left of the first side of a hole belongs to the
+        // smallest outer ring around it
+        let inside = left_cell_center(hole[0], hole[1]);
+        let owner = (0..outers.len())
+            .filter(|k| ring_contains(&outer_rings[*k], inside.0, inside.1))
+            .min_by(|a, b| outers[*a].1.partial_cmp(&outers[*b].1).unwrap());
+        if let Some(owner) = owner {
+            polygons[owner].push(hole);
+        }
+    }
+
+    let unproject = |(i, j): (f64, f64)| {
+        let (x, y) = (min_x + i * cell, min_y + j * cell);
+        (
+            origin.0 + y / METERS_PER_DEGREE,
+            origin.1 + x / (METERS_PER_DEGREE * cos_lat),
+        )
+    };
+    Ok(polygons
+        .into_iter()
+        .map(|polygon| {
+            polygon
+                .into_iter()
+                .map(|ring| {
+                    let mut ring: Vec<(f64, f64)> = simplify_ring(&as_f64(&ring), 1.0)
+                        .into_iter()
+                        .map(unproject)
+                        .collect();
+                    ring.push(ring[0]);
+                    ring
+                })
+                .collect()
+        })
+        .collect())
+}
+
+// directions of cell sides: +x, +y, -x, -y
+const STEPS: [(i64, i64); 4] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+
+// traces the sides between filled and empty cells with the filled cell on the
+// left; where two filled cells only touch at a corner the trace turns left, so
+// every ring is simple
+fn trace_rings<F: Fn(i64, i64) -> bool>(
+    width: i64,
+    height: i64,
+    is_filled: &F,
+) -> Vec<Vec<(i64, i64)>> {
+    // sides as (corner, direction)
+    let mut sides = Vec::new();
+    for j in 0..height {
+        for i in 0..width {
+            if !is_filled(i, j) {
+                continue;
+            }
+            if !is_filled(i, j - 1) {
+                sides.push(((i, j), 0));
+            }
+            if !is_filled(i + 1, j) {
+                sides.push(((i + 1, j), 1));
+            }
+            if !is_filled(i, j + 1) {
+                sides.push(((i + 1, j + 1), 2));
+            }
+            if !is_filled(i - 1, j) {
+                sides.push(((i, j + 1), 3));
+            }
+        }
+    }
+    let all: HashSet<((i64, i64), usize)> = sides.iter().cloned().collect();
+    let mut used = HashSet::new();
+
+    let mut rings = Vec::new();
+    for first in sides {
+        if used.contains(&first) {
+            continue;
+        }
+
+        // corners where the direction changes
+        let mut ring = Vec::new();
+        let mut side = first;
+        loop {
+            used.insert(side);
+            let ((x, y), direction) = side;
+            let corner = (x + STEPS[direction].0, y + STEPS[direction].1);
+            let next = [(direction + 1) % 4, direction, (direction + 3) % 4]
+                .iter()
+                .map(|d| (corner, *d))
+                .find(|s| all.contains(s))
+                .unwrap();
+            if next.1 != direction {
+                ring.push(corner);
+            }
+            if used.contains(&next) {
+                break;
+            }
+            side = next;
+        }
+        rings.push(ring);
+    }
+    rings
+}
+
+// Douglas-Peucker on a closed ring, split at the corner farthest from the
+// first one; rings too small to simplify are returned unchanged
+fn simplify_ring(ring: &[(f64, f64)], tolerance: f64) -> Vec<(f64, f64)> {
+    if ring.len() <= 4 {
+        return ring.to_vec();
+    }
+    let distance = |a: (f64, f64), b: (f64, f64)| (b.0 - a.0).hypot(b.1 - a.1);
+    let far = (1..ring.len())
+        .max_by(|a, b| {
+            distance(ring[0], ring[*a])
+                .partial_cmp(&distance(ring[0], ring[*b]))
+                .unwrap()
+        })
+        .unwrap();
+
+    let mut keep = vec![false; ring.len() + 1];
+    keep[0] = true;
+    keep[far] = true;
+    let point = |k: usize| ring[k % ring.len()];
+    let mut stack = vec![(0, far), (far, ring.len())];
+    while let Some((first, last)) = stack.pop() {
+        let (a, b) = (point(first), point(last));
+        let length = distance(a, b);
+        let offset = |p: (f64, f64)| {
+            if length > 0.0 {
+                ((b.0 - a.0) * (a.1 - p.1) - (a.0 - p.0) * (b.1 - a.1)).abs() / length
+            } else {
+                distance(a, p)
+            }
+        };
+        let farthest = (first + 1..last)
+            .max_by(|x, y| offset(point(*x)).partial_cmp(&offset(point(*y))).unwrap());
+        if let Some(k) = farthest.filter(|k| offset(point(*k)) > tolerance) {
+            keep[k] = true;
+            stack.push((first, k));
+            stack.push((k, last));
+        }
+    }
+
+    let simplified: Vec<(f64, f64)> = (0..ring.len())
+        .filter(|k| keep[*k])
+        .map(|k| ring[k])
+        .collect();
+    if simplified.len() < 3 {
+        ring.to_vec()
+    } else {
+        simplified
+    }
+}
+
+fn signed_area(ring: &[(i64, i64)]) -> f64 {
+    let mut area = 0;
+    for (k, a) in ring.iter().enumerate() {
+        let b = ring[(k + 1) % ring.len()];
+        area += a.0 * b.1 - b.0 * a.1;
+    }
+    area as f64 / 2.0
+}
+
+fn left_cell_center(a: (i64, i64), b: (i64, i64)) -> (f64, f64) {
+    let (dx, dy) = ((b.0 - a.0).signum(), (b.1 - a.1).signum());
+    (
+        a.0 as f64 + 0.5 * dx as f64 - 0.5 * dy as f64,
+        a.1 as f64 + 0.5 * dy as f64 + 0.5 * dx as f64,
+    )
+}
+
+pub fn write_geojson<E: AsRef<EdgeData>>(
+    g: &Graph<NodeData, E>,
+    isochrones: &[Isochrone],
+    filename: String,
+) -> Result<(), io::Error> {
+    let mut f = io::BufWriter::new(File::create(filename)?);
+    write_features(g, isochrones, &mut f)?;
+    f.flush()
+}
+
+// a FeatureCollection with a MultiPolygon hull and LineString edge parts per
+// threshold, and the reachable nodes of the largest threshold as Points; the
+// features are told apart by their "kind" property
+pub fn write_features<E: AsRef<EdgeData>, W: Write>(
+    g: &Graph<NodeData, E>,
+    isochrones: &[Isochrone],
+    f: &mut W,
+) -> Result<(), io::Error> {
+    f.write_all(b"{\"type\":\"FeatureCollection\",\"features\":[\n")?;
+
+    let mut first = true;
+    let mut separator = |f: &mut W| -> Result<(), io::Error> {
+        if !first {
+            f.write_all(b",\n")?;
+        }
+        first = false;
+        Ok(())
+    };
+    let coordinates = |points: &[(f64, f64)]| -> String {
+        points
+            .iter()
+            .map(|(lat, lon)| coordinate(*lat, *lon))
+            .collect::<Vec<String>>()
+            .join(",")
+    };
+
+    for isochrone in isochrones {
+        let polygons = isochrone
+            .hull
+            .iter()
+            .map(|polygon| {
+                let rings = polygon
+                    .iter()
+                    .map(|ring| format!("[{}]", coordinates(ring)))
+                    .collect::<Vec<String>>()
+                    .join(",");
+                format!("[{}]", rings)
+            })
+            .collect::<Vec<String>>()
+            .join(",");
+
+        separator(f)?;
+        write!(
+            f,
+            "{{\"type\":\"Feature\",\"geometry\":{{\"type\":\"MultiPolygon\",\"coordinates\":[{}]}},\
+             \"properties\":{{\"kind\":\"hull\",\"threshold\":{}}}}}",
+            polygons, isochrone.threshold
+        )?;
+    }
+
+    for isochrone in isochrones {
+        for part in &isochrone.edges {
+            let geometry = cut_geometry(&edge_geometry(g, part.edge), part.start, part.end);
+            separator(f)?;
+            write!(
+                f,
+                "{{\"type\":\"Feature\",\"geometry\":{{\"type\":\"LineString\",\"coordinates\":[{}]}},\
+                 \"properties\":{{\"kind\":\"edge\",\"threshold\":{},\"id\":{},\"start\":{:.6},\
+                 \"end\":{:.6}}}}}",
+                coordinates(&geometry),
+                isochrone.threshold,
+                part.edge.0,
+                part.start,
+                part.end
+            )?;
+        }
+    }
+
+    if let Some(isochrone) = isochrones.last() {
+        for (node, cost) in &isochrone.nodes {
+            let data = &g.nodes[node.0 as usize].data;
+            separator(f)?;
+            write!(
+                f,
+                "{{\"type\":\"Feature\",\"geometry\":{{\"type\":\"Point\",\"coordinates\":{}}},\
+                 \"properties\":{{\"kind\":\"node\",\"id\":{},\"cost\":{:.3}}}}}",
+                coordinate(data.lat, data.lon),
+                node.0,
+                cost
+            )?;
+        }
+    }
+
+    f.write_all(b"\n]}\n")?;
+
+    Ok(())
+}
+
+// TESTS
+#[cfg(test)]
+use osmpbfreader::{NodeId, WayId};
+#[cfg(test)]
+use util;
+
+// a square of four nodes with 0.002 degrees sides, one-way from 0 to 1 and
+// two-way around the rest
+#[cfg(test)]
+fn create_graph() -> Graph<NodeData, EdgeData> {
+    let mut g = Graph {
+        nodes: Vec::new(),
+        edges: Vec::new(),
+    };
+    let corners = [
+        (52.0, 13.0),
+        (52.0, 13.002),
+        (52.002, 13.002),
+        (52.002, 13.0),
+    ];
+    for (i, (lat, lon)) in corners.iter().enumerate() {
+        g.add_node(NodeData {
+            osm_id: NodeId(i as i64),
+            lat: *lat,
+            lon: *lon,
+        });
+    }
+    for (s, t, bidirectional) in [(0, 1, false), (1, 2, true), (2, 3, true), (3, 0, true)].iter() {
+        let (a, b) = (corners[*s], corners[*t]);
+        let data = EdgeData {
+            osm_way_id: WayId(0),
+            osm_way_piece: 0,
+            name: String::new(),
+            length: util::haversine_distance(a, b),
+            street_type: "residential".to_string(),
+            max_speed: 30,
+            bidirectional: *bidirectional,
+            geometry: vec![a, b],
+        };
+        g.add_edge(
+            GraphNodeId(*s as u32),
+            GraphNodeId(*t as u32),
+            data,
+            *bidirectional,
+        );
+    }
+    g
+}
+
+#[test]
+fn should_parse_thresholds() {
+    assert!(parse_thresholds("600, 300,900,300").unwrap() == vec![300.0, 600.0, 900.0]);
+    assert!(
+        parse_thresholds("300,-5").err().unwrap().to_string()
+            == "invalid argument: invalid threshold: -5"
+    );
+}
+
+#[test]
+fn should_cut_geometry() {
+    let geometry = vec![(0.0, 0.0), (0.0, 1.0), (0.0, 3.0)];
+
+    let cut = cut_geometry(&geometry, 0.25, 0.75);
+    assert!(cut.len() == 3);
+    assert!((cut[0].1 - 0.75).abs() < 1e-6);
+    assert!(cut[1] == (0.0, 1.0));
+    assert!((cut[2].1 - 2.25).abs() < 1e-6);
+
+    assert!(cut_geometry(&geometry, 0.0, 1.0) == geometry);
+}
+
+#[test]
+fn should_cut_edges_at_the_threshold() {
+    let g = create_graph();
+    let (width, height) = (g.edges[0].data.length, g.edges[3].data.length);
+
+    let thresholds = [width * 0.5, height + width * 0.25];
+    let isochrones = compute(&g, GraphNodeId(0), &thresholds, Cost::Distance, 20.0).unwrap();
+
+    // only the start node, the one-way edge and the edge to node 3 are cut
+    let small = &isochrones[0];
+    assert!(small.nodes.len() == 1);
+    assert!(small.edges.len() == 2);
+    assert!(small.edges[0].edge == GraphEdgeId(0) && small.edges[0].start == 0.0);
+    assert!((small.edges[0].end - 0.5).abs() < 1e-6);
+    assert!(small.edges[1].edge == GraphEdgeId(3) && small.edges[1].end == 1.0);
+    assert!((small.edges[1].start - (1.0 - width * 0.5 / height)).abs() < 1e-6);
+
+    // 0, 1 and 3 reached, the edges to 2 are cut from both sides
+    let large = &isochrones[1];
+    assert!(large.nodes.iter().map(|(n, _)| n.0).collect::<Vec<u32>>() == vec![0, 1, 3]);
+    assert!(large.edges.len() == 4);
+    assert!(
+        large.edges[0]
+            == EdgePart {
+                edge: GraphEdgeId(0),
+                start: 0.0,
+                end: 1.0
+            }
+    );
+    assert!(large.edges[1].edge == GraphEdgeId(1) && large.edges[1].start == 0.0);
+    assert!((large.edges[1].end - (height - width * 0.75) / height).abs() < 1e-6);
+    assert!(large.edges[2].edge == GraphEdgeId(2) && large.edges[2].end == 1.0);
+    let top = g.edges[2].data.length;
+    assert!((large.edges[2].start - (1.0 - width * 0.25 / top)).abs() < 1e-6);
+    assert!(
+        large.edges[3]
+            == EdgePart {
+                edge: GraphEdgeId(3),
+                start: 0.0,
+                end: 1.0
+            }
+    );
+}
+
+#[test]
+fn should_build_hulls_with_holes() {
+    let g = create_graph();
+
+    let isochrones = compute(&g, GraphNodeId(0), &[10.0, 10000.0], Cost::Distance, 20.0).unwrap();
+
+    // a blob around the start node
+    assert!(isochrones[0].hull.len() == 1);
+    assert!(isochrones[0].hull[0].len() == 1);
+
+    // the whole square is reachable, its inside is a hole
+    let hull = &isochrones[1].hull;
+    assert!(hull.len() == 1);
+    assert!(hull[0].len() == 2);
+    assert!(hull[0][0].first() == hull[0][0].last());
+    let as_xy = |ring: &[(f64, f64)]| -> Vec<(f64, f64)> {
+        ring.iter().map(|(lat, lon)| (*lon, *lat)).collect()
+    };
+    assert!(ring_contains(&as_xy(&hull[0][0]), 13.0, 52.0));
+    assert!(ring_contains(&as_xy(&hull[0][0]), 13.001, 52.001));
+    assert!(ring_contains(&as_xy(&hull[0][1]), 13.001, 52.001));
+    assert!(!ring_contains(&as_xy(&hull[0][1]), 13.0, 52.0));
+
+    // the blob around the start node is a polygon, not a staircase of cells
+    assert!(isochrones[0].hull[0][0].len() < 12);
+
+    // a raster of 1cm cells over the whole square is refused
+    let error = compute(&g, GraphNodeId(0), &[10000.0], Cost::Distance, 0.02)
+        .err()
+        .unwrap();
+    assert!(error.exit_code() == 2);
+    assert!(error.to_string().contains("use at least"));
+}
+
+#[test]
+fn should_simplify_staircases() {
+    // a 2 x 2 cells square with one cell cut from a corner is kept, a
+    // staircase along the diagonal of a 10 x 10 square becomes a triangle
+    let square = [
+        (0.0, 0.0),
+        (2.0, 0.0),
+        (2.0, 1.0),
+        (1.0, 1.0),
+        (1.0, 2.0),
+        (0.0, 2.0),
+    ];
+    assert!(simplify_ring(&square, 1.0).len() >= 3);
+    let mut staircase = vec![(0.0, 0.0), (10.0, 0.0), (10.0, 1.0)];
+    for k in 1..10 {
+        staircase.push((10.0 - k as f64, k as f64));
+        staircase.push((10.0 - k as f64, k as f64 + 1.0));
+    }
+    staircase.push((0.0, 10.0));
+    let simplified = simplify_ring(&staircase, 1.0);
+    assert!(simplified.len() == 3);
+    for corner in [(0.0, 0.0), (10.0, 0.0)].iter() {
+        assert!(simplified.contains(corner));
+    }
+}
+
+#[test]
+fn should_write_geojson_features() {
+    let g = create_graph();
+    let isochrones = compute(&g, GraphNodeId(0), &[10.0, 100.0], Cost::Distance, 20.0).unwrap();
+
+    let mut output = Vec::new();
+    write_features(&g, &isochrones, &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+
+    assert!(output.starts_with("{\"type\":\"FeatureCollection\""));
+    assert!(output.matches("\"kind\":\"hull\"").count() == 2);
+    assert!(output.contains("\"properties\":{\"kind\":\"hull\",\"threshold\":100}"));
+    assert!(output.contains("\"kind\":\"node\",\"id\":0,\"cost\":0.000"));
+    assert!(
+        output
+            .matches("\"kind\":\"edge\",\"threshold\":100")
+            .count()
+            == 2
+    );
+}
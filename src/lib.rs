@@ -0,0 +1,35 @@
+extern crate bzip2;
+extern crate flate2;
+#[macro_use]
+extern crate log;
+extern crate osmpbfreader;
+extern crate quick_xml;
+extern crate smartstring;
+extern crate yaml_rust;
+
+pub mod binary;
+pub mod clipping;
+pub mod connected_components;
+pub mod contraction;
+pub mod contraction_hierarchy;
+pub mod error;
+pub mod geojson;
+pub mod graph;
+pub mod graph_data;
+pub mod isochrone;
+pub mod matrix;
+pub mod network_type;
+pub mod node_store;
+pub mod osm_convert;
+pub mod osm_parse_config;
+pub mod osm_parse_config_creator;
+pub mod osm_reader;
+pub mod osm_xml_reader;
+pub mod output;
+pub mod restrictions;
+pub mod routing;
+pub mod spatial_index;
+mod util;
+
+pub use network_type::NetworkType;
+pub use osm_parse_config::OSMParseConfig;
@@ -1,93 +1,438 @@
 #[macro_use]
 extern crate clap;
-extern crate osmpbfreader;
-#[macro_use]
-extern crate lazy_static;
-extern crate yaml_rust;
-extern crate smartstring;
-use clap::App;
-use network_type::{get_network_type, NetworkType};
-
-mod graph;
-mod graph_data;
-mod network_type;
-mod osm_convert;
-mod osm_parse_config;
-mod osm_parse_config_creator;
-mod osm_reader;
-mod output;
-mod util;
-
-lazy_static! {
-    static ref VERBOSE: bool = {
-        let yaml = load_yaml!("cli.yaml");
-        let arg_matches = App::from_yaml(yaml).get_matches();
-
-        arg_matches.is_present("verbose")
-    };
-    static ref NETWORK_TYPE: NetworkType = {
-        let yaml = load_yaml!("cli.yaml");
-        let arg_matches = App::from_yaml(yaml).get_matches();
+extern crate log;
+extern crate osmtoroadgraph;
 
-        let network_type = get_network_type(arg_matches.value_of("network").unwrap());
-        println!("converting OSM to network_type: {:?}", network_type);
-        network_type
-    };
-    static ref NO_LLC: bool = {
-        let yaml = load_yaml!("cli.yaml");
-        let arg_matches = App::from_yaml(yaml).get_matches();
+use clap::{App, AppSettings, ArgMatches};
+use osmtoroadgraph::clipping::{get_clip_policy, parse_bbox, read_poly_file, Clip};
+use osmtoroadgraph::connected_components::{
+    default_component_type, get_component_type, ComponentType,
+};
+use osmtoroadgraph::contraction_hierarchy::ContractionHierarchy;
+use osmtoroadgraph::error::Error;
+use osmtoroadgraph::graph::{Graph, GraphNodeId};
+use osmtoroadgraph::graph_data::{EdgeData, NodeData};
+use osmtoroadgraph::network_type::{get_network_type, NetworkType};
+use osmtoroadgraph::output::get_format_version;
+use osmtoroadgraph::routing::{get_cost, parse_coordinate, Cost};
+use osmtoroadgraph::spatial_index::SpatialIndex;
+use osmtoroadgraph::{
+    binary, connected_components, contraction, contraction_hierarchy, geojson, isochrone, matrix,
+    osm_convert, osm_parse_config_creator, osm_reader, output, restrictions, routing,
+};
+use std::path::Path;
+use std::process;
 
-        arg_matches.is_present("nolcc")
-    };
-    static ref CONTRACT: bool = {
-        let yaml = load_yaml!("cli.yaml");
-        let arg_matches = App::from_yaml(yaml).get_matches();
+// prints the progress messages of the library to stdout and its warnings to stderr
+struct Logger;
 
-        arg_matches.is_present("contract")
-    };
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Info && metadata.target().starts_with("osmtoroadgraph")
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if record.level() <= log::Level::Warn {
+            eprintln!("WARNING: {}", record.args());
+        } else {
+            println!("{}", record.args());
+        }
+    }
+
+    fn flush(&self) {}
 }
 
+static LOGGER: Logger = Logger;
+
 fn main() {
+    log::set_logger(&LOGGER).expect("the logger is only set once");
+    log::set_max_level(log::LevelFilter::Info);
+
+    if let Err(error) = run() {
+        eprintln!("ERROR: {}", error);
+        process::exit(error.exit_code());
+    }
+}
+
+fn run() -> Result<(), Error> {
     let yaml = load_yaml!("cli.yaml");
-    let arg_matches = App::from_yaml(yaml).get_matches();
-
-    let default_config_str = include_str!("config.yaml");
-    let default_config =
-        osm_parse_config_creator::create_config_from_string(default_config_str.to_owned());
-    let config = arg_matches
-        .value_of("config")
-        .map_or(default_config, |input_file| {
+    let arg_matches = match App::from_yaml(yaml)
+        .setting(AppSettings::ColorNever)
+        .get_matches_safe()
+    {
+        Ok(arg_matches) => arg_matches,
+        // --help and --version are printed to stdout and exit with 0
+        Err(ref e) if !e.use_stderr() => e.exit(),
+        Err(e) => {
+            return Err(Error::Argument(
+                e.message.trim_start_matches("error: ").to_string(),
+            ))
+        }
+    };
+
+    if let Some(route_matches) = arg_matches.subcommand_matches("route") {
+        route(route_matches)
+    } else if let Some(matrix_matches) = arg_matches.subcommand_matches("matrix") {
+        matrix(matrix_matches)
+    } else if let Some(isochrone_matches) = arg_matches.subcommand_matches("isochrone") {
+        isochrone(isochrone_matches)
+    } else {
+        convert(&arg_matches)
+    }
+}
+
+fn convert(arg_matches: &ArgMatches) -> Result<(), Error> {
+    let network_type = get_network_type(arg_matches.value_of("network").unwrap())?;
+    println!("converting OSM to network_type: {:?}", network_type);
+
+    let no_lcc = arg_matches.is_present("nolcc");
+    let lcc_type: ComponentType = match arg_matches.value_of("lcc_type") {
+        Some(lcc_type) => get_component_type(lcc_type)?,
+        None => default_component_type(&network_type),
+    };
+    let contract = arg_matches.is_present("contract");
+    let format = arg_matches.value_of("format").unwrap();
+    let format_version = get_format_version(arg_matches.value_of("format_version").unwrap())?;
+    let ch_cost = match arg_matches.value_of("ch") {
+        Some(cost) => Some(get_cost(cost)?),
+        None => None,
+    };
+
+    let clip_region = match (arg_matches.value_of("bbox"), arg_matches.value_of("poly")) {
+        (Some(bbox), _) => Some(parse_bbox(bbox)?),
+        (None, Some(poly)) => Some(read_poly_file(poly).map_err(|e| e.in_file(poly))?),
+        (None, None) => None,
+    };
+    let clip = match clip_region {
+        Some(region) => Some(Clip {
+            region,
+            policy: get_clip_policy(arg_matches.value_of("clip_policy").unwrap())?,
+        }),
+        None => None,
+    };
+
+    let config = match arg_matches.value_of("config") {
+        Some(input_file) => {
             osm_parse_config_creator::create_config_from_file(input_file.to_owned())
-        });
+                .map_err(|e| e.in_file(input_file))?
+        }
+        None => osm_parse_config_creator::create_default_config()?,
+    };
 
     //process
     let in_filename = arg_matches.value_of("input").unwrap();
-    let (nodes, ways) = osm_reader::read_osm(in_filename, &config);
-    let graph = osm_convert::convert(nodes, ways, &config);
+    let (nodes, ways, relations) =
+        osm_reader::read_osm(in_filename, &config, &network_type, clip.as_ref())
+            .map_err(|e| e.in_file(in_filename))?;
+    let graph = osm_convert::convert(nodes, ways, &config, &network_type)?;
+    let graph = if no_lcc {
+        graph
+    } else {
+        connected_components::reduce_to_largest_component(graph, lcc_type)
+    };
+    let osm_restrictions = restrictions::parse_restrictions(&relations, &network_type);
+    let turn_restrictions = restrictions::map_restrictions(&osm_restrictions, &graph);
 
     //output
-    let out_filename = create_out_filename(in_filename, &(NETWORK_TYPE));
-    let out_filename_names = format!("{}_names", out_filename);
-
-    println!("writing graph to {}", out_filename);
-    let output_result = output::write(&graph, out_filename);
-    match output_result {
-        Ok(_) => (),
-        Err(y) => println!("ERROR: {}", y),
-    };
+    let out_filename = create_out_filename(in_filename, &network_type);
+    let out_filename_restrictions = format!("{}_restrictions", out_filename);
 
-    println!("writing street names to {}", out_filename_names);
-    let output_result = output::write_names(&graph, out_filename_names);
-    match output_result {
-        Ok(_) => (),
-        Err(y) => println!("ERROR: {}", y),
-    };
+    match format {
+        "geojson" => {
+            let out_filename_geojson = format!("{}.geojson", out_filename);
+            println!("writing graph to {}", out_filename_geojson);
+            geojson::write(&graph, out_filename_geojson)?;
+        }
+        "binary" => {
+            let out_filename_binary = format!("{}.bin", out_filename);
+            println!("writing graph to {}", out_filename_binary);
+            binary::write(&graph, out_filename_binary)?;
+        }
+        "csv" => {
+            let out_filename_nodes = format!("{}_nodes.csv", out_filename);
+            let out_filename_edges = format!("{}_edges.csv", out_filename);
+            println!(
+                "writing graph to {} and {}",
+                out_filename_nodes, out_filename_edges
+            );
+            output::write_csv(&graph, out_filename_nodes, out_filename_edges)?;
+        }
+        _ => {
+            let out_filename_names = format!("{}_names", out_filename);
+            let out_filename_node_ids = format!("{}_node_osm_ids", out_filename);
+            let out_filename_edge_ids = format!("{}_edge_osm_ids", out_filename);
+
+            println!("writing graph to {}", out_filename);
+            output::write(&graph, out_filename.clone(), format_version)?;
+
+            println!("writing street names to {}", out_filename_names);
+            output::write_names(&graph, out_filename_names)?;
+
+            println!("writing osm node ids to {}", out_filename_node_ids);
+            output::write_node_osm_ids(&graph, out_filename_node_ids)?;
+
+            println!("writing osm way ids to {}", out_filename_edge_ids);
+            output::write_edge_osm_ids(&graph, out_filename_edge_ids)?;
+        }
+    }
+
+    println!("writing turn restrictions to {}", out_filename_restrictions);
+    output::write_restrictions(&turn_restrictions, out_filename_restrictions)?;
+
+    if let Some(ch_cost) = ch_cost {
+        let ch = ContractionHierarchy::new(&graph, ch_cost);
+        // next to the file that `route` reads the graph from
+        let out_filename_ch = match format {
+            "binary" => format!("{}.bin_ch", out_filename),
+            _ => format!("{}_ch", out_filename),
+        };
+        println!("writing contraction hierarchy to {}", out_filename_ch);
+        contraction_hierarchy::write(&ch, out_filename_ch)?;
+    }
+
+    if contract {
+        let contracted_graph = contraction::contract(&graph);
+        let out_filename_contracted = format!("{}c", out_filename);
+
+        match format {
+            "geojson" => {
+                let out_filename_geojson = format!("{}.geojson", out_filename_contracted);
+                println!("writing contracted graph to {}", out_filename_geojson);
+                geojson::write(&contracted_graph, out_filename_geojson)?;
+            }
+            "binary" => {
+                let out_filename_binary = format!("{}.bin", out_filename_contracted);
+                println!("writing contracted graph to {}", out_filename_binary);
+                binary::write(&contracted_graph, out_filename_binary)?;
+            }
+            "csv" => {
+                let out_filename_nodes = format!("{}_nodes.csv", out_filename_contracted);
+                let out_filename_edges = format!("{}_edges.csv", out_filename_contracted);
+                println!(
+                    "writing contracted graph to {} and {}",
+                    out_filename_nodes, out_filename_edges
+                );
+                output::write_csv(&contracted_graph, out_filename_nodes, out_filename_edges)?;
+            }
+            _ => {
+                let out_filename_contracted_names = format!("{}_names", out_filename_contracted);
+                let out_filename_contracted_node_ids =
+                    format!("{}_node_osm_ids", out_filename_contracted);
+                let out_filename_contracted_edge_ids =
+                    format!("{}_edge_osm_ids", out_filename_contracted);
+
+                println!("writing contracted graph to {}", out_filename_contracted);
+                output::write(
+                    &contracted_graph,
+                    out_filename_contracted.clone(),
+                    format_version,
+                )?;
+
+                println!(
+                    "writing contracted street names to {}",
+                    out_filename_contracted_names
+                );
+                output::write_names(&contracted_graph, out_filename_contracted_names)?;
+
+                println!(
+                    "writing contracted osm node ids to {}",
+                    out_filename_contracted_node_ids
+                );
+                output::write_node_osm_ids(&contracted_graph, out_filename_contracted_node_ids)?;
+
+                println!(
+                    "writing contracted osm way ids to {}",
+                    out_filename_contracted_edge_ids
+                );
+                output::write_edge_osm_ids(&contracted_graph, out_filename_contracted_edge_ids)?;
+            }
+        }
+
+        // needed to expand contracted edges again, whatever the format
+        let out_filename_contracted_nodes = format!("{}_original_nodes", out_filename_contracted);
+        let out_filename_contracted_ways = format!("{}_original_ways", out_filename_contracted);
+
+        println!(
+            "writing original node ids of contracted edges to {}",
+            out_filename_contracted_nodes
+        );
+        output::write_original_nodes(&contracted_graph, out_filename_contracted_nodes)?;
+
+        println!(
+            "writing original osm way ids of contracted edges to {}",
+            out_filename_contracted_ways
+        );
+        output::write_original_ways(&contracted_graph, out_filename_contracted_ways)?;
+    }
 
     //TODO:
-    // - compute LCC
-    // - compute contraction
     // - add code coverage
     // - fill README.MD
+
+    Ok(())
+}
+
+fn route(arg_matches: &ArgMatches) -> Result<(), Error> {
+    let graph_filename = arg_matches.value_of("graph").unwrap();
+    let from = parse_coordinate(arg_matches.value_of("from").unwrap())?;
+    let to = parse_coordinate(arg_matches.value_of("to").unwrap())?;
+    let cost = get_cost(arg_matches.value_of("cost").unwrap())?;
+
+    let graph = read_graph(graph_filename)?;
+
+    let index = SpatialIndex::new(&graph);
+    let (s, t) = match (
+        index.nearest_node(from.0, from.1),
+        index.nearest_node(to.0, to.1),
+    ) {
+        (Some(s), Some(t)) => (s.node, t.node),
+        _ => return Err(no_nodes(graph_filename)),
+    };
+
+    let path = match arg_matches.value_of("algorithm").unwrap() {
+        "dijkstra" => routing::dijkstra(&graph, s, t, cost),
+        "ch" => read_hierarchy(graph_filename, &graph, cost)?.query(&graph, s, t)?,
+        _ => routing::astar(&graph, s, t, cost),
+    };
+
+    let path = match path {
+        Some(path) => path,
+        None => {
+            println!("no route from node {} to node {}", s.0, t.0);
+            return Ok(());
+        }
+    };
+
+    println!("path:");
+    for node in &path.nodes {
+        let data = &graph.nodes[node.0 as usize].data;
+        println!("{} {:.6} {:.6}", node.0, data.lat, data.lon);
+    }
+    println!("distance: {:.1}m", path.distance);
+    println!("duration: {:.1}s", path.duration);
+
+    Ok(())
+}
+
+fn matrix(arg_matches: &ArgMatches) -> Result<(), Error> {
+    let graph_filename = arg_matches.value_of("graph").unwrap();
+    let locations_filename = arg_matches.value_of("locations").unwrap();
+    let out_filename = arg_matches.value_of("output").unwrap();
+    let cost = get_cost(arg_matches.value_of("cost").unwrap())?;
+
+    let graph = read_graph(graph_filename)?;
+    let locations = matrix::read_locations(locations_filename)
+        .map_err(|e| Error::from(e).in_file(locations_filename))?;
+
+    let index = SpatialIndex::new(&graph);
+    let snap = |coordinates: &[(f64, f64)]| -> Option<Vec<GraphNodeId>> {
+        coordinates
+            .iter()
+            .map(|(lat, lon)| index.nearest_node(*lat, *lon).map(|m| m.node))
+            .collect()
+    };
+    let (sources, targets) = match (snap(&locations.sources), snap(&locations.targets)) {
+        (Some(sources), Some(targets)) => (sources, targets),
+        _ => return Err(no_nodes(graph_filename)),
+    };
+
+    // the hierarchy is optional here, without it every source runs a Dijkstra
+    let ch = if Path::new(&format!("{}_ch", graph_filename)).exists() {
+        match read_hierarchy(graph_filename, &graph, cost) {
+            Ok(ch) => Some(ch),
+            Err(y) => {
+                println!("not using contraction hierarchy: {}", y);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let table = matrix::compute(&graph, &sources, &targets, cost, ch.as_ref());
+
+    println!("writing matrix to {}", out_filename);
+    match arg_matches.value_of("output_format").unwrap() {
+        "binary" => matrix::write_binary(&table, cost, out_filename.to_owned())?,
+        _ => matrix::write_csv(&table, cost, out_filename.to_owned())?,
+    }
+
+    Ok(())
+}
+
+fn isochrone(arg_matches: &ArgMatches) -> Result<(), Error> {
+    let graph_filename = arg_matches.value_of("graph").unwrap();
+    let from = parse_coordinate(arg_matches.value_of("from").unwrap())?;
+    let thresholds = isochrone::parse_thresholds(arg_matches.value_of("thresholds").unwrap())?;
+    let cost = get_cost(arg_matches.value_of("cost").unwrap())?;
+    let hull_radius = arg_matches
+        .value_of("hull_radius")
+        .unwrap()
+        .parse::<f64>()
+        .ok()
+        .filter(|radius| radius.is_finite() && *radius > 0.0)
+        .ok_or_else(|| {
+            Error::Argument("hull radius has to be a positive number of meters".to_string())
+        })?;
+    let out_filename = arg_matches.value_of("output").unwrap();
+
+    let graph = read_graph(graph_filename)?;
+
+    let s = match SpatialIndex::new(&graph).nearest_node(from.0, from.1) {
+        Some(s) => s.node,
+        None => return Err(no_nodes(graph_filename)),
+    };
+
+    let isochrones = isochrone::compute(&graph, s, &thresholds, cost, hull_radius)?;
+    for isochrone in &isochrones {
+        println!(
+            "threshold {}: {} nodes, {} edge parts, {} polygons",
+            isochrone.threshold,
+            isochrone.nodes.len(),
+            isochrone.edges.len(),
+            isochrone.hull.len()
+        );
+    }
+
+    println!("writing isochrones to {}", out_filename);
+    isochrone::write_geojson(&graph, &isochrones, out_filename.to_owned())?;
+
+    Ok(())
+}
+
+// reads a graph in the binary or in the text format
+fn read_graph(filename: &str) -> Result<Graph<NodeData, EdgeData>, Error> {
+    println!("reading graph from {}", filename);
+    binary::is_binary_file(filename)
+        .and_then(|is_binary| {
+            if is_binary {
+                binary::read(filename)
+            } else {
+                output::read(filename)
+            }
+        })
+        .map_err(|e| Error::from(e).in_file(filename))
+}
+
+// reads the contraction hierarchy written next to the graph file
+fn read_hierarchy(
+    graph_filename: &str,
+    graph: &Graph<NodeData, EdgeData>,
+    cost: Cost,
+) -> Result<ContractionHierarchy, Error> {
+    let filename = format!("{}_ch", graph_filename);
+    println!("reading contraction hierarchy from {}", filename);
+    let ch =
+        contraction_hierarchy::read(&filename).map_err(|e| Error::from(e).in_file(&filename))?;
+    ch.check(graph, cost).map_err(|e| e.in_file(&filename))?;
+    Ok(ch)
+}
+
+fn no_nodes(graph_filename: &str) -> Error {
+    Error::Data(format!("{} has no nodes", graph_filename))
 }
 
 fn create_out_filename(in_filename: &str, network_type: &NetworkType) -> String {
@@ -0,0 +1,211 @@
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::time::Instant;
+
+use contraction_hierarchy::ContractionHierarchy;
+use graph::{Graph, GraphNodeId};
+use graph_data::{EdgeData, NodeData};
+use routing::{one_to_many, Cost};
+use util;
+use util::{invalid_data, write_u32};
+
+// Binary Matrix File, all values little-endian:
+//
+//   magic                "ROADMTRX"
+//   version              u32
+//   cost                 u8, 0 for distance in meters and 1 for travel time in seconds
+//   #sources, #targets   u32 each
+//   table                #sources x #targets f64, row by row; infinity if unreachable
+pub const MAGIC: &[u8; 8] = b"ROADMTRX";
+pub const VERSION: u32 = 1;
+
+pub struct Locations {
+    // (lat, lon)
+    pub sources: Vec<(f64, f64)>,
+    pub targets: Vec<(f64, f64)>,
+}
+
+pub fn read_locations(filename: &str) -> Result<Locations, io::Error> {
+    parse_locations(io::BufReader::new(File::open(filename)?))
+}
+
+// one "source,lat,lon" or "target,lat,lon" record per line; a leading header
+// line is skipped
+pub fn parse_locations<R: BufRead>(f: R) -> Result<Locations, io::Error> {
+    let mut locations = Locations {
+        sources: Vec::new(),
+        targets: Vec::new(),
+    };
+
+    for (i, line) in f.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || (i == 0 && line.starts_with("role")) {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split(',').map(|x| x.trim()).collect();
+        if fields.len() != 3 {
+            return Err(invalid_data(format!(
+                "line {}: expected role,lat,lon, found {}",
+                i + 1,
+                line
+            )));
+        }
+        let coordinate = match (fields[1].parse::<f64>(), fields[2].parse::<f64>()) {
+            (Ok(lat), Ok(lon)) => (lat, lon),
+            _ => {
+                return Err(invalid_data(format!(
+                    "line {}: invalid coordinate: {},{}",
+                    i + 1,
+                    fields[1],
+                    fields[2]
+                )))
+            }
+        };
+        match fields[0] {
+            "source" => locations.sources.push(coordinate),
+            "target" => locations.targets.push(coordinate),
+            x => {
+                return Err(invalid_data(format!(
+                    "line {}: role has to be source or target: {}",
+                    i + 1,
+                    x
+                )))
+            }
+        }
+    }
+
+    Ok(locations)
+}
+
+// costs between all sources and targets, one row per source; uses the
+// hierarchy if one is given, otherwise one Dijkstra per source
+pub fn compute<E: AsRef<EdgeData> + Sync>(
+    g: &Graph<NodeData, E>,
+    sources: &[GraphNodeId],
+    targets: &[GraphNodeId],
+    cost: Cost,
+    ch: Option<&ContractionHierarchy>,
+) -> Vec<Vec<Option<f64>>> {
+    let now = Instant::now();
+
+    let table = match ch {
+        Some(ch) => ch.many_to_many(sources, targets),
+        None => util::parallel_map(sources, |s| one_to_many(g, *s, targets, cost)),
+    };
+
+    info!(
+        "computed {}x{} matrix: {}s",
+        sources.len(),
+        targets.len(),
+        now.elapsed().as_secs()
+    );
+
+    table
+}
+
+pub fn write_csv(
+    table: &[Vec<Option<f64>>],
+    cost: Cost,
+    filename: String,
+) -> Result<(), io::Error> {
+    let mut f = io::BufWriter::new(File::create(filename)?);
+    write_csv_table(table, cost, &mut f)?;
+    f.flush()
+}
+
+// one "source,target,cost" record per pair with the indices of the source and
+// the target in the input; the cost is empty if the target is unreachable
+pub fn write_csv_table<W: Write>(
+    table: &[Vec<Option<f64>>],
+    cost: Cost,
+    f: &mut W,
+) -> Result<(), io::Error> {
+    let column = match cost {
+        Cost::Distance => "distance",
+        Cost::TravelTime => "duration",
+    };
+    writeln!(f, "source,target,{}", column)?;
+
+    for (s, row) in table.iter().enumerate() {
+        for (t, value) in row.iter().enumerate() {
+            match value {
+                Some(value) => writeln!(f, "{},{},{:.3}", s, t, value)?,
+                None => writeln!(f, "{},{},", s, t)?,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn write_binary(
+    table: &[Vec<Option<f64>>],
+    cost: Cost,
+    filename: String,
+) -> Result<(), io::Error> {
+    let mut f = io::BufWriter::new(File::create(filename)?);
+    write_binary_table(table, cost, &mut f)?;
+    f.flush()
+}
+
+pub fn write_binary_table<W: Write>(
+    table: &[Vec<Option<f64>>],
+    cost: Cost,
+    f: &mut W,
+) -> Result<(), io::Error> {
+    f.write_all(MAGIC)?;
+    write_u32(f, VERSION)?;
+    f.write_all(&[match cost {
+        Cost::Distance => 0,
+        Cost::TravelTime => 1,
+    }])?;
+    write_u32(f, table.len() as u32)?;
+    write_u32(f, table.first().map_or(0, |row| row.len()) as u32)?;
+
+    for row in table {
+        for value in row {
+            f.write_all(&value.unwrap_or(f64::INFINITY).to_le_bytes())?;
+        }
+    }
+
+    Ok(())
+}
+
+// TESTS
+#[test]
+fn should_parse_locations() {
+    let input = "role,lat,lon\nsource,52.5,13.4\ntarget, -1.5 , 2\n\nsource,0,0\n";
+    let locations = parse_locations(input.as_bytes()).unwrap();
+
+    assert!(locations.sources == vec![(52.5, 13.4), (0.0, 0.0)]);
+    assert!(locations.targets == vec![(-1.5, 2.0)]);
+
+    let error = |input: &str| parse_locations(input.as_bytes()).err().unwrap().to_string();
+    assert!(error("source,52.5\n") == "line 1: expected role,lat,lon, found source,52.5");
+    assert!(error("role,lat,lon\ntarget,x,1\n") == "line 2: invalid coordinate: x,1");
+    assert!(error("depot,1,2\n") == "line 1: role has to be source or target: depot");
+}
+
+#[test]
+fn should_write_tables() {
+    let table = vec![vec![Some(1.5), None], vec![Some(0.0), Some(2.25)]];
+
+    let mut csv = Vec::new();
+    write_csv_table(&table, Cost::TravelTime, &mut csv).unwrap();
+    assert!(
+        String::from_utf8(csv).unwrap()
+            == "source,target,duration\n0,0,1.500\n0,1,\n1,0,0.000\n1,1,2.250\n"
+    );
+
+    let mut binary = Vec::new();
+    write_binary_table(&table, Cost::Distance, &mut binary).unwrap();
+    assert!(binary.len() == 8 + 4 + 1 + 4 + 4 + 4 * 8);
+    assert!(binary.starts_with(MAGIC));
+    assert!(binary[12] == 0);
+    assert!(binary[13..17] == 2u32.to_le_bytes());
+    assert!(binary[17..21] == 2u32.to_le_bytes());
+    assert!(binary[29..37] == f64::INFINITY.to_le_bytes());
+    assert!(binary[45..53] == 2.25f64.to_le_bytes());
+}
@@ -1,3 +1,5 @@
+use error::Error;
+
 #[derive(PartialEq, Eq, Hash, Debug)]
 pub enum NetworkType {
     Pedestrian,
@@ -5,11 +7,14 @@ pub enum NetworkType {
     Bicycle,
 }
 
-pub fn get_network_type(network_type: &str) -> NetworkType {
+pub fn get_network_type(network_type: &str) -> Result<NetworkType, Error> {
     match network_type {
-        "pedestrian" | "p" => NetworkType::Pedestrian,
-        "car" | "c" => NetworkType::Car,
-        "bicycle" | "b" => NetworkType::Bicycle,
-        _ => panic!("did not recognize type: {}", network_type),
+        "pedestrian" | "p" => Ok(NetworkType::Pedestrian),
+        "car" | "c" => Ok(NetworkType::Car),
+        "bicycle" | "b" => Ok(NetworkType::Bicycle),
+        _ => Err(Error::Argument(format!(
+            "did not recognize type: {}",
+            network_type
+        ))),
     }
 }
@@ -0,0 +1,107 @@
+use osmpbfreader::NodeId;
+
+// marks nodes whose coordinates have not been read (yet)
+const MISSING: i32 = i32::MIN;
+
+// coordinates of a fixed set of OSM nodes, sorted by id; uses 16 bytes per node
+// instead of a full `osmpbfreader::Node` in a hash map
+pub struct NodeStore {
+    ids: Vec<NodeId>,
+    // (lat, lon) in decimicro degrees
+    coordinates: Vec<(i32, i32)>,
+}
+
+impl NodeStore {
+    pub fn with_ids(mut ids: Vec<NodeId>) -> NodeStore {
+        ids.sort_unstable();
+        ids.dedup();
+        ids.shrink_to_fit();
+
+        let coordinates = vec![(MISSING, MISSING); ids.len()];
+        NodeStore { ids, coordinates }
+    }
+
+    // sets the coordinates of a node; returns false if the node is not part of the store
+    pub fn set(&mut self, id: NodeId, decimicro_lat: i32, decimicro_lon: i32) -> bool {
+        match self.ids.binary_search(&id) {
+            Ok(i) => {
+                self.coordinates[i] = (decimicro_lat, decimicro_lon);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    // (lat, lon) in degrees
+    pub fn get(&self, id: &NodeId) -> Option<(f64, f64)> {
+        let i = self.ids.binary_search(id).ok()?;
+        let (lat, lon) = self.coordinates[i];
+        if lat == MISSING {
+            None
+        } else {
+            Some((lat as f64 * 1e-7, lon as f64 * 1e-7))
+        }
+    }
+
+    // adds nodes that are not part of the input, e.g. where a way is cut
+    pub fn insert(&mut self, nodes: &[(NodeId, f64, f64)]) {
+        let mut entries: Vec<(NodeId, (i32, i32))> = self
+            .ids
+            .iter()
+            .cloned()
+            .zip(self.coordinates.iter().cloned())
+            .collect();
+        entries.extend(nodes.iter().map(|(id, lat, lon)| {
+            (
+                *id,
+                ((lat * 1e7).round() as i32, (lon * 1e7).round() as i32),
+            )
+        }));
+        entries.sort_unstable_by_key(|(id, _)| *id);
+        entries.dedup_by_key(|(id, _)| *id);
+
+        let (ids, coordinates) = entries.into_iter().unzip();
+        self.ids = ids;
+        self.coordinates = coordinates;
+    }
+
+    pub fn contains(&self, id: &NodeId) -> bool {
+        self.get(id).is_some()
+    }
+
+    // number of nodes with known coordinates
+    pub fn len(&self) -> usize {
+        self.coordinates
+            .iter()
+            .filter(|(lat, _)| *lat != MISSING)
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// TESTS
+#[test]
+fn should_only_store_requested_nodes() {
+    let mut store = NodeStore::with_ids(vec![NodeId(3), NodeId(1), NodeId(3)]);
+
+    assert!(store.set(NodeId(3), 10_000_000, -20_000_000));
+    assert!(!store.set(NodeId(2), 0, 0));
+
+    assert!(store.len() == 1);
+    assert!(store.contains(&NodeId(3)));
+    assert!(!store.contains(&NodeId(1)));
+    assert!(!store.contains(&NodeId(2)));
+
+    let (lat, lon) = store.get(&NodeId(3)).unwrap();
+    assert!((lat - 1.0).abs() < 1e-9);
+    assert!((lon + 2.0).abs() < 1e-9);
+
+    store.insert(&[(NodeId(-1), 0.5, 0.25)]);
+    assert!(store.len() == 2);
+    let (lat, lon) = store.get(&NodeId(-1)).unwrap();
+    assert!((lat - 0.5).abs() < 1e-9 && (lon - 0.25).abs() < 1e-9);
+    assert!(store.get(&NodeId(3)).is_some() && !store.contains(&NodeId(1)));
+}
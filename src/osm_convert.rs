@@ -3,66 +3,217 @@ use std::time::Instant;
 
 use smartstring::alias::String;
 
-use graph::Graph;
+use error::Error;
+use graph::{Graph, GraphNodeId};
 use graph_data::{EdgeData, NodeData};
+use network_type::NetworkType;
+use node_store::NodeStore;
 use osm_parse_config;
-use osmpbfreader::{Node, NodeId, Way};
+use osmpbfreader::{NodeId, Tags, Way};
 use util;
 
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum Direction {
+    Both,
+    Forward,
+    Backward,
+    // e.g. reversible lanes, that can not be used reliably
+    Neither,
+}
+
 pub fn convert(
-    nodes: HashMap<NodeId, Node>,
+    nodes: NodeStore,
     ways: Vec<Way>,
     osm_parse_config: &osm_parse_config::OSMParseConfig,
-) -> Graph<NodeData, EdgeData> {
+    network_type: &NetworkType,
+) -> Result<Graph<NodeData, EdgeData>, Error> {
     let now = Instant::now();
 
+    // numbering must not depend on the order the ways were read in: edges are
+    // numbered by way id and position along the way, nodes by their OSM id.
+    // The pieces of a way cut by clipping share its id and stay in order.
+    let mut ways = ways;
+    ways.sort_by_key(|way| way.id);
+
+    let mut pieces = Vec::with_capacity(ways.len());
+    for (i, way) in ways.iter().enumerate() {
+        let piece = match i {
+            0 => 0,
+            _ if ways[i - 1].id == way.id => pieces[i - 1] + 1,
+            _ => 0,
+        };
+        pieces.push(piece);
+    }
+
+    let ways: Vec<(Way, u32, Direction)> = ways
+        .into_iter()
+        .zip(pieces)
+        .map(|(way, piece)| {
+            let direction = get_direction(&way.tags, network_type);
+            (way, piece, direction)
+        })
+        .filter(|(way, _, direction)| *direction != Direction::Neither && way.nodes.len() >= 2)
+        .collect();
+
     let mut g: Graph<NodeData, EdgeData> = Graph {
         nodes: Vec::new(),
         edges: Vec::new(),
     };
 
-    let mut node_map = HashMap::new();
+    // a node becomes a graph node if it starts or ends a way, or if it is
+    // shared by more than one way (or visited twice by the same way)
+    let mut node_usage: HashMap<NodeId, u32> = HashMap::new();
+    for (way, _, _) in &ways {
+        for node_id in &way.nodes {
+            *node_usage.entry(*node_id).or_insert(0) += 1;
+        }
+    }
 
-    for (k, v) in nodes {
-        let data = NodeData {
-            lat: v.lat(),
-            lon: v.lon(),
-        };
+    let mut split_nodes: Vec<NodeId> = Vec::new();
+    for (way, _, _) in &ways {
+        let last_index = way.nodes.len() - 1;
+        for (i, node_id) in way.nodes.iter().enumerate() {
+            if i == 0 || i == last_index || node_usage[node_id] > 1 {
+                split_nodes.push(*node_id);
+            }
+        }
+    }
+    split_nodes.sort_unstable();
+    split_nodes.dedup();
+
+    let missing_node = |way: &Way, node_id: &NodeId| {
+        Error::Data(format!(
+            "way {} refers to node {}, which is missing in the input",
+            way.id.0, node_id.0
+        ))
+    };
+    for (way, _, _) in &ways {
+        if let Some(node_id) = way.nodes.iter().find(|x| !nodes.contains(x)) {
+            return Err(missing_node(way, node_id));
+        }
+    }
 
-        let new_node_id = g.add_node(data);
-        node_map.insert(k, new_node_id);
+    let mut node_map: HashMap<NodeId, GraphNodeId> = HashMap::new();
+    for node_id in split_nodes {
+        let (lat, lon) = nodes.get(&node_id).unwrap();
+        let new_node_id = g.add_node(NodeData {
+            osm_id: node_id,
+            lat,
+            lon,
+        });
+        node_map.insert(node_id, new_node_id);
     }
 
     let empty_name = String::from("");
 
-    for way in ways {
+    for (way, piece, direction) in ways {
         let name = way.tags.get("name").unwrap_or(&empty_name).to_string();
-        let street_type = way.tags.get("highway").unwrap().to_string();
-        let max_speed = parse_speed(way.tags.get("maxspeed"), &street_type, osm_parse_config);
-        let bidirectional = way.tags.get("oneway").map(|x| x != "yes").unwrap_or(true);
-
-        let data = EdgeData {
-            name,
-            street_type,
-            max_speed,
-            bidirectional,
+        let street_type = match way.tags.get("highway") {
+            Some(street_type) => street_type.to_string(),
+            None => return Err(Error::Data(format!("way {} has no highway tag", way.id.0))),
         };
-
-        let s = node_map.get(way.nodes.first().unwrap()).unwrap();
-        let t = node_map.get(way.nodes.last().unwrap()).unwrap();
-
-        g.add_edge(*s, *t, data, bidirectional);
+        let max_speed = parse_speed(way.tags.get("maxspeed"), &street_type, osm_parse_config);
+        let bidirectional = direction == Direction::Both;
+
+        let last_index = way.nodes.len() - 1;
+        let mut segment_start = way.nodes[0];
+        let mut geometry = Vec::new();
+
+        for (i, node_id) in way.nodes.iter().enumerate() {
+            let coordinates = nodes.get(node_id).unwrap();
+            geometry.push(coordinates);
+
+            let is_split_node = i == last_index || node_usage[node_id] > 1;
+            if i == 0 || !is_split_node {
+                continue;
+            }
+
+            let mut s = node_map[&segment_start];
+            let mut t = node_map[node_id];
+            let next_geometry = vec![coordinates];
+
+            if direction == Direction::Backward {
+                std::mem::swap(&mut s, &mut t);
+                geometry.reverse();
+            }
+
+            let data = EdgeData {
+                osm_way_id: way.id,
+                osm_way_piece: piece,
+                name: name.clone(),
+                length: util::polyline_length(&geometry),
+                street_type: street_type.clone(),
+                max_speed,
+                bidirectional,
+                geometry,
+            };
+            g.add_edge(s, t, data, bidirectional);
+
+            segment_start = *node_id;
+            geometry = next_geometry;
+        }
     }
 
-    println!(
+    info!(
         "converted to a graph with {} edges and {} nodes: {}s",
         g.edges.len(),
         g.nodes.len(),
         now.elapsed().as_secs()
     );
-    println!();
 
-    g
+    Ok(g)
+}
+
+fn get_direction(tags: &Tags, network_type: &NetworkType) -> Direction {
+    if *network_type == NetworkType::Pedestrian {
+        return Direction::Both;
+    }
+
+    if *network_type == NetworkType::Bicycle {
+        let contraflow_cycleway = [
+            "cycleway",
+            "cycleway:left",
+            "cycleway:right",
+            "cycleway:both",
+        ]
+        .iter()
+        .filter_map(|k| tags.get(*k))
+        .any(|v| v.starts_with("opposite"));
+        if contraflow_cycleway {
+            return Direction::Both;
+        }
+
+        if let Some(direction) = tags.get("oneway:bicycle").and_then(|x| parse_oneway(x)) {
+            return direction;
+        }
+    }
+
+    if let Some(direction) = tags.get("oneway").and_then(|x| parse_oneway(x)) {
+        return direction;
+    }
+
+    // oneway is implied on motorways and roundabouts
+    let is_roundabout = tags
+        .get("junction")
+        .map(|x| x == "roundabout" || x == "circular")
+        .unwrap_or(false);
+    let is_motorway = tags.contains("highway", "motorway");
+
+    if is_roundabout || is_motorway {
+        Direction::Forward
+    } else {
+        Direction::Both
+    }
+}
+
+fn parse_oneway(value: &str) -> Option<Direction> {
+    match value {
+        "yes" | "true" | "1" => Some(Direction::Forward),
+        "-1" | "reverse" => Some(Direction::Backward),
+        "no" | "false" | "0" => Some(Direction::Both),
+        "reversible" | "alternating" => Some(Direction::Neither),
+        _ => None,
+    }
 }
 
 fn parse_speed(
@@ -70,46 +221,116 @@ fn parse_speed(
     street_type: &str,
     osm_parse_config: &osm_parse_config::OSMParseConfig,
 ) -> u8 {
-    if speed.is_none() {
-        return osm_parse_config.default_speed(street_type);
-    }
-
-    let speed_info = speed.unwrap();
+    let speed_info = match speed {
+        Some(speed_info) => speed_info,
+        None => return osm_parse_config.default_speed(street_type),
+    };
 
     // try to convert it to u8; if valid return
-    if speed_info.parse::<u8>().is_ok() {
-        return speed_info.parse::<u8>().unwrap();
+    if let Ok(speed) = speed_info.parse::<u8>() {
+        return speed;
     }
 
-    if speed_info.contains("walk") {
-        osm_parse_config.default_walking_speed()
+    let digits = util::keep_characters(speed_info, "0123456789");
+    let speed = if speed_info.contains("walk") {
+        Some(osm_parse_config.default_walking_speed())
     } else if speed_info.contains("none") {
-        osm_parse_config.default_speed(street_type)
+        Some(osm_parse_config.default_speed(street_type))
     } else if speed_info.contains("mph") || speed_info.contains("mp/h") {
         let fac = 1.609_344;
-        let digits = util::keep_characters(speed_info, "0123456789");
-        let value = digits.parse::<f64>().unwrap() * fac;
-        value as u8
+        digits
+            .parse::<f64>()
+            .ok()
+            .map(|value| (value * fac).min(u8::MAX as f64) as u8)
     } else if speed_info.contains("kph")
         || speed_info.contains("kmh")
         || speed_info.contains("km/h")
     {
-        let digits = util::keep_characters(speed_info, "0123456789");
-
-        digits.parse::<u8>().unwrap()
+        digits.parse::<u8>().ok()
     } else {
-        println!(
+        None
+    };
+
+    speed.unwrap_or_else(|| {
+        warn!(
             "error while parsing max speed! Did not recognize: {}! Fallback used!",
             speed_info
         );
         osm_parse_config.default_speed(street_type)
-    }
+    })
 }
 
 // TESTS
 #[cfg(test)]
+use osmpbfreader::WayId;
+#[cfg(test)]
+use output;
+#[cfg(test)]
 use std::collections::HashSet;
 
+#[test]
+fn should_split_ways_at_shared_nodes() {
+    let (config, highway, _highway_speed, _default_walking_speed) = create_config();
+
+    let nodes = create_nodes(&[1, 2, 3, 4, 5]);
+    let ways = vec![
+        create_way(1, &[1, 2, 3], &highway),
+        create_way(2, &[4, 2, 5], &highway),
+    ];
+
+    let g = convert(nodes, ways, &config, &NetworkType::Car).unwrap();
+
+    assert!(g.nodes.len() == 5);
+    assert!(g.edges.len() == 4);
+}
+
+#[test]
+fn should_keep_intermediate_nodes_as_geometry() {
+    let (config, highway, _highway_speed, _default_walking_speed) = create_config();
+
+    let nodes = create_nodes(&[1, 2, 3, 4]);
+    let ways = vec![create_way(1, &[1, 2, 3, 4], &highway)];
+
+    let g = convert(nodes, ways, &config, &NetworkType::Car).unwrap();
+
+    assert!(g.nodes.len() == 2);
+    assert!(g.edges.len() == 1);
+    assert!(g.edges[0].data.geometry.len() == 4);
+    assert!((g.edges[0].data.geometry[1].0 - 0.2).abs() < 1e-9);
+
+    let direct = util::haversine_distance((0.1, 0.1), (0.4, 0.4));
+    assert!((g.edges[0].data.length - direct).abs() < 1.0);
+}
+
+#[test]
+fn should_split_way_visiting_a_node_twice() {
+    let (config, highway, _highway_speed, _default_walking_speed) = create_config();
+
+    let nodes = create_nodes(&[1, 2, 3, 4]);
+    let ways = vec![create_way(1, &[1, 2, 3, 4, 2], &highway)];
+
+    let g = convert(nodes, ways, &config, &NetworkType::Car).unwrap();
+
+    assert!(g.nodes.len() == 2);
+    assert!(g.edges.len() == 2);
+    assert!(g.edges[1].data.geometry.len() == 4);
+}
+
+#[test]
+fn should_reject_ways_with_missing_nodes() {
+    let (config, highway, _highway_speed, _default_walking_speed) = create_config();
+
+    let nodes = create_nodes(&[1, 2]);
+    let ways = vec![create_way(7, &[1, 2, 3], &highway)];
+
+    let error = convert(nodes, ways, &config, &NetworkType::Car)
+        .err()
+        .unwrap();
+
+    assert!(error.exit_code() == 7);
+    assert!(error.to_string().contains("way 7 refers to node 3"));
+}
+
 #[test]
 fn should_return_default_speed_for_highway_when_speed_is_none() {
     let (config, highway, highway_speed, _default_walking_speed) = create_config();
@@ -181,6 +402,18 @@ fn should_return_kmh_speed() {
     }
 }
 
+#[test]
+fn should_fall_back_for_speeds_out_of_range() {
+    let (config, highway, highway_speed, _default_walking_speed) = create_config();
+
+    for speed_str in ["300 km/h", "mph"].iter() {
+        let speed_str = String::from(*speed_str);
+        let result: u8 = parse_speed(Some(&speed_str), &highway, &config);
+
+        assert!(result == highway_speed);
+    }
+}
+
 #[test]
 fn should_return_speed() {
     let (config, highway, _highway_speed, _default_walking_speed) = create_config();
@@ -220,8 +453,239 @@ fn create_config() -> (osm_parse_config::OSMParseConfig, String, u8, u8) {
     let mut allowed_highways = HashSet::new();
     allowed_highways.insert(highway.to_owned());
 
-    let config =
-        osm_parse_config::OSMParseConfig::new(HashMap::new(), max_speed, default_walking_speed);
+    let config = osm_parse_config::OSMParseConfig::new(
+        HashMap::new(),
+        max_speed,
+        default_walking_speed,
+        HashMap::new(),
+        HashSet::new(),
+        HashSet::new(),
+    );
+
+    (
+        config,
+        String::from(highway),
+        highway_speed,
+        default_walking_speed,
+    )
+}
+
+#[test]
+fn should_parse_explicit_oneway_tags() {
+    let cases = [
+        ("yes", Direction::Forward),
+        ("true", Direction::Forward),
+        ("1", Direction::Forward),
+        ("-1", Direction::Backward),
+        ("reverse", Direction::Backward),
+        ("no", Direction::Both),
+        ("false", Direction::Both),
+        ("0", Direction::Both),
+        ("reversible", Direction::Neither),
+        ("alternating", Direction::Neither),
+        ("garbage", Direction::Both),
+    ];
+
+    for (value, expected) in cases.iter() {
+        let tags = create_tags(&[("highway", "residential"), ("oneway", value)]);
+        assert!(get_direction(&tags, &NetworkType::Car) == *expected);
+        assert!(get_direction(&tags, &NetworkType::Bicycle) == *expected);
+        assert!(get_direction(&tags, &NetworkType::Pedestrian) == Direction::Both);
+    }
+}
+
+#[test]
+fn should_imply_oneway_for_roundabouts_and_motorways() {
+    let roundabout = create_tags(&[("highway", "primary"), ("junction", "roundabout")]);
+    let circular = create_tags(&[("highway", "primary"), ("junction", "circular")]);
+    let motorway = create_tags(&[("highway", "motorway")]);
+    let motorway_no = create_tags(&[("highway", "motorway"), ("oneway", "no")]);
+    let primary = create_tags(&[("highway", "primary")]);
+
+    assert!(get_direction(&roundabout, &NetworkType::Car) == Direction::Forward);
+    assert!(get_direction(&circular, &NetworkType::Car) == Direction::Forward);
+    assert!(get_direction(&motorway, &NetworkType::Car) == Direction::Forward);
+    assert!(get_direction(&motorway_no, &NetworkType::Car) == Direction::Both);
+    assert!(get_direction(&primary, &NetworkType::Car) == Direction::Both);
+    assert!(get_direction(&roundabout, &NetworkType::Pedestrian) == Direction::Both);
+}
+
+#[test]
+fn should_apply_bicycle_specific_oneway_tags() {
+    let oneway_bicycle_no = create_tags(&[("oneway", "yes"), ("oneway:bicycle", "no")]);
+    let oneway_bicycle_yes = create_tags(&[("oneway:bicycle", "yes")]);
+    let opposite = create_tags(&[("oneway", "yes"), ("cycleway", "opposite")]);
+    let opposite_lane = create_tags(&[("oneway", "-1"), ("cycleway:left", "opposite_lane")]);
+
+    assert!(get_direction(&oneway_bicycle_no, &NetworkType::Bicycle) == Direction::Both);
+    assert!(get_direction(&oneway_bicycle_no, &NetworkType::Car) == Direction::Forward);
+    assert!(get_direction(&oneway_bicycle_yes, &NetworkType::Bicycle) == Direction::Forward);
+    assert!(get_direction(&oneway_bicycle_yes, &NetworkType::Car) == Direction::Both);
+    assert!(get_direction(&opposite, &NetworkType::Bicycle) == Direction::Both);
+    assert!(get_direction(&opposite, &NetworkType::Car) == Direction::Forward);
+    assert!(get_direction(&opposite_lane, &NetworkType::Bicycle) == Direction::Both);
+    assert!(get_direction(&opposite_lane, &NetworkType::Car) == Direction::Backward);
+}
+
+#[test]
+fn should_reverse_edges_of_backward_oneways() {
+    let (config, highway, _highway_speed, _default_walking_speed) = create_config();
+
+    let nodes = create_nodes(&[1, 2, 3]);
+    let mut way = create_way(1, &[1, 2, 3], &highway);
+    way.tags.insert("oneway".into(), "-1".into());
+
+    let g = convert(nodes, vec![way], &config, &NetworkType::Car).unwrap();
+
+    assert!(g.edges.len() == 1);
+    assert!(!g.edges[0].data.bidirectional);
+    assert!(g.nodes[g.edges[0].s.0 as usize].data.osm_id == NodeId(3));
+    assert!(g.nodes[g.edges[0].t.0 as usize].data.osm_id == NodeId(1));
+    assert!((g.edges[0].data.geometry[0].0 - 0.3).abs() < 1e-9);
+    assert!(g.nodes[g.edges[0].t.0 as usize].get_edges().is_empty());
+}
+
+#[test]
+fn should_drop_reversible_ways() {
+    let (config, highway, _highway_speed, _default_walking_speed) = create_config();
+
+    let nodes = create_nodes(&[1, 2]);
+    let mut way = create_way(1, &[1, 2], &highway);
+    way.tags.insert("oneway".into(), "reversible".into());
+
+    let g = convert(nodes, vec![way], &config, &NetworkType::Car).unwrap();
+
+    assert!(g.edges.is_empty());
+}
+
+#[test]
+fn should_convert_deterministically() {
+    let (config, highway, _highway_speed, _default_walking_speed) = create_config();
+
+    let create_fixture = || {
+        let nodes = create_nodes(&[1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        let mut ways = vec![
+            create_way(10, &[1, 2, 3], &highway),
+            create_way(11, &[4, 5, 6], &highway),
+            create_way(12, &[7, 8, 9], &highway),
+            create_way(13, &[1, 4, 7], &highway),
+            create_way(14, &[2, 5, 8], &highway),
+            create_way(15, &[3, 6, 9, 3], &highway),
+        ];
+        ways[1].tags.insert("oneway".into(), "-1".into());
+        ways[2].tags.insert("name".into(), "Main Street".into());
+        (nodes, ways)
+    };
+
+    let (nodes, ways) = create_fixture();
+    let first = convert(nodes, ways, &config, &NetworkType::Car).unwrap();
+
+    let (nodes, mut ways) = create_fixture();
+    ways.reverse();
+    ways.swap(0, 3);
+    let second = convert(nodes, ways, &config, &NetworkType::Car).unwrap();
+
+    let mut first_output = Vec::new();
+    output::write_graph(&first, &mut first_output, output::FormatVersion::Corrected).unwrap();
+    let mut second_output = Vec::new();
+    output::write_graph(
+        &second,
+        &mut second_output,
+        output::FormatVersion::Corrected,
+    )
+    .unwrap();
+
+    assert!(!first_output.is_empty());
+    assert!(first_output == second_output);
+    assert!(first.nodes[0].data.osm_id == NodeId(1));
+    assert!(first.edges[0].data.osm_way_id == WayId(10));
+}
+
+#[test]
+fn should_convert_fixtures_to_identical_bytes() {
+    use osm_parse_config_creator;
+    use osm_reader;
+
+    let config = osm_parse_config_creator::create_default_config().unwrap();
+    let convert_fixture = |filename: &str, network_type: &NetworkType| {
+        let (nodes, ways, _) = osm_reader::read_osm(filename, &config, network_type, None).unwrap();
+        let g = convert(nodes, ways, &config, network_type).unwrap();
+        let mut bytes = Vec::new();
+        output::write_graph(&g, &mut bytes, output::FormatVersion::Corrected).unwrap();
+        bytes
+    };
 
-    return (config, String::from(highway), highway_speed, default_walking_speed);
+    let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+    let mut converted = 0;
+    for entry in std::fs::read_dir(fixtures).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|x| x != "osm") {
+            continue;
+        }
+        let filename = path.to_str().unwrap();
+        for network_type in [
+            NetworkType::Car,
+            NetworkType::Bicycle,
+            NetworkType::Pedestrian,
+        ]
+        .iter()
+        {
+            let first = convert_fixture(filename, network_type);
+            let second = convert_fixture(filename, network_type);
+            // more than the 10 header lines and the counts
+            assert!(first.iter().filter(|x| **x == b'\n').count() > 12);
+            assert!(first == second);
+        }
+        converted += 1;
+    }
+    assert!(converted > 0);
+}
+
+#[test]
+fn should_number_pieces_of_cut_ways() {
+    let (config, highway, _highway_speed, _default_walking_speed) = create_config();
+    let nodes = create_nodes(&[1, 2, 3, 4, 5]);
+    let ways = vec![
+        create_way(10, &[1, 2], &highway),
+        create_way(11, &[2, 3], &highway),
+        create_way(10, &[4, 5], &highway),
+    ];
+
+    let g = convert(nodes, ways, &config, &NetworkType::Car).unwrap();
+
+    let pieces: Vec<(i64, u32)> = g
+        .edges
+        .iter()
+        .map(|e| (e.data.osm_way_id.0, e.data.osm_way_piece))
+        .collect();
+    assert!(pieces == vec![(10, 0), (10, 1), (11, 0)]);
+}
+
+#[cfg(test)]
+fn create_tags(tags: &[(&str, &str)]) -> Tags {
+    tags.iter()
+        .map(|(k, v)| ((*k).into(), (*v).into()))
+        .collect()
+}
+
+#[cfg(test)]
+fn create_nodes(ids: &[i64]) -> NodeStore {
+    let mut nodes = NodeStore::with_ids(ids.iter().map(|&id| NodeId(id)).collect());
+    for id in ids {
+        let coordinate = (id * 1_000_000) as i32;
+        nodes.set(NodeId(*id), coordinate, coordinate);
+    }
+    nodes
+}
+
+#[cfg(test)]
+fn create_way(id: i64, node_ids: &[i64], highway: &str) -> Way {
+    let mut tags = Tags::new();
+    tags.insert("highway".into(), highway.into());
+
+    Way {
+        id: WayId(id),
+        tags,
+        nodes: node_ids.iter().map(|&x| NodeId(x)).collect(),
+    }
 }
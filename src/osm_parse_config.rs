@@ -1,31 +1,64 @@
 use std::collections::{HashMap, HashSet};
 
+use network_type::NetworkType;
+
 pub struct OSMParseConfig {
-    allowed_highways: HashMap<::NetworkType, HashSet<String>>,
+    allowed_highways: HashMap<NetworkType, HashSet<String>>,
     max_speed: HashMap<String, u8>,
     default_walking_speed: u8,
+    access_hierarchy: HashMap<NetworkType, Vec<String>>,
+    allowed_access: HashSet<String>,
+    denied_access: HashSet<String>,
 }
 
 impl OSMParseConfig {
     pub fn new(
-        allowed_highways: HashMap<::NetworkType, HashSet<String>>,
+        allowed_highways: HashMap<NetworkType, HashSet<String>>,
         max_speed: HashMap<String, u8>,
         default_walking_speed: u8,
+        access_hierarchy: HashMap<NetworkType, Vec<String>>,
+        allowed_access: HashSet<String>,
+        denied_access: HashSet<String>,
     ) -> OSMParseConfig {
         OSMParseConfig {
             allowed_highways,
             max_speed,
             default_walking_speed,
+            access_hierarchy,
+            allowed_access,
+            denied_access,
         }
     }
 
-    pub fn is_allowed(&self, key: &::NetworkType, value: &str) -> bool {
+    pub fn is_allowed(&self, key: &NetworkType, value: &str) -> bool {
         self.allowed_highways
             .get(key)
             .unwrap_or(&HashSet::new())
             .contains(value)
     }
 
+    // whether any network type may use the highway value; access tags can
+    // only open highways of these values to other network types
+    pub fn is_routable(&self, value: &str) -> bool {
+        self.allowed_highways.values().any(|x| x.contains(value))
+    }
+
+    // access tag keys for the network type, ordered from general to specific
+    pub fn access_hierarchy(&self, key: &NetworkType) -> &[String] {
+        self.access_hierarchy
+            .get(key)
+            .map(|x| &x[..])
+            .unwrap_or(&[])
+    }
+
+    pub fn is_access_allowed(&self, value: &str) -> bool {
+        self.allowed_access.contains(value)
+    }
+
+    pub fn is_access_denied(&self, value: &str) -> bool {
+        self.denied_access.contains(value)
+    }
+
     pub fn default_walking_speed(&self) -> u8 {
         self.default_walking_speed
     }
@@ -34,7 +67,8 @@ impl OSMParseConfig {
         match self.max_speed.get(street_type) {
             Some(result) => *result,
             None => {
-                println!("unknown street type: {}", street_type);
+                warn!("unknown street type: {}", street_type);
+                // the config creator makes sure the entry exists
                 *self.max_speed.get("unknown").unwrap()
             }
         }
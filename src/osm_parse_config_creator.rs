@@ -1,4 +1,5 @@
-use network_type::get_network_type;
+use error::Error;
+use network_type::{get_network_type, NetworkType};
 use osm_parse_config::OSMParseConfig;
 
 use std::collections::{HashMap, HashSet};
@@ -6,60 +7,195 @@ use std::fs::File;
 use std::io::prelude::*;
 use yaml_rust::yaml::{Yaml, YamlLoader};
 
-pub fn create_config_from_file(filename: String) -> OSMParseConfig {
-    let mut file = File::open(filename).unwrap();
+pub fn create_default_config() -> Result<OSMParseConfig, Error> {
+    create_config_from_string(include_str!("config.yaml").to_owned())
+}
+
+pub fn create_config_from_file(filename: String) -> Result<OSMParseConfig, Error> {
+    let mut file = File::open(filename)?;
     let mut file_content = String::new();
-    file.read_to_string(&mut file_content).unwrap();
+    file.read_to_string(&mut file_content)?;
 
     create_config_from_string(file_content)
 }
 
-pub fn create_config_from_string(config: String) -> OSMParseConfig {
-    let docs = YamlLoader::load_from_str(&config).unwrap();
-    let doc = &docs[0];
+pub fn create_config_from_string(config: String) -> Result<OSMParseConfig, Error> {
+    let docs = YamlLoader::load_from_str(&config).map_err(|e| Error::Config(e.to_string()))?;
+    let doc = docs
+        .first()
+        .ok_or_else(|| Error::Config("empty config".to_string()))?;
 
-    let allowed_highways = parse_allowed_highways(doc);
-    let max_speed_map = parse_max_speeds(doc);
-    let default_walking_speed = parse_default_walking_speed(doc);
+    let allowed_highways = parse_allowed_highways(doc)?;
+    let max_speed_map = parse_max_speeds(doc)?;
+    let default_walking_speed = parse_default_walking_speed(doc)?;
+    let access_hierarchy = parse_access_hierarchy(doc)?;
+    let allowed_access = parse_access_values(doc, "allowed_access");
+    let denied_access = parse_access_values(doc, "denied_access");
 
-    OSMParseConfig::new(allowed_highways, max_speed_map, default_walking_speed)
+    Ok(OSMParseConfig::new(
+        allowed_highways,
+        max_speed_map,
+        default_walking_speed,
+        access_hierarchy,
+        allowed_access,
+        denied_access,
+    ))
 }
 
-fn parse_allowed_highways(doc: &Yaml) -> HashMap<::NetworkType, HashSet<String>> {
-    let mut allowed_highways: HashMap<::NetworkType, HashSet<String>> = HashMap::new();
+// sections are lists of single-entry maps, e.g. "- car: primary, secondary"
+fn entries<'a>(doc: &'a Yaml, section: &str) -> Result<Vec<(&'a str, &'a Yaml)>, Error> {
+    let list = match doc[section] {
+        Yaml::Array(ref list) => list,
+        Yaml::BadValue => return Err(Error::Config(format!("missing section: {}", section))),
+        _ => return Err(Error::Config(format!("{} has to be a list", section))),
+    };
 
-    let allowed_highways_file = doc["allowed_highways"].as_vec().unwrap();
-    for entry in allowed_highways_file {
-        for (key, types) in entry.as_hash().unwrap() {
-            let types = types
+    let mut entries = Vec::new();
+    for entry in list {
+        let entry = entry
+            .as_hash()
+            .ok_or_else(|| Error::Config(format!("{} has to be a list of key: value", section)))?;
+        for (key, value) in entry {
+            let key = key
                 .as_str()
-                .unwrap()
-                .split(", ")
-                .map(|x| x.to_string())
-                .collect::<HashSet<String>>();
-
-            let network_type = get_network_type(key.as_str().unwrap());
-            allowed_highways.insert(network_type, types);
+                .ok_or_else(|| Error::Config(format!("{} has a key that is no string", section)))?;
+            entries.push((key, value));
         }
     }
+    Ok(entries)
+}
+
+fn parse_network_type(section: &str, key: &str) -> Result<NetworkType, Error> {
+    get_network_type(key)
+        .map_err(|_| Error::Config(format!("unknown network type in {}: {}", section, key)))
+}
+
+fn parse_list(section: &str, key: &str, value: &Yaml) -> Result<Vec<String>, Error> {
+    value.as_str().map(split_list).ok_or_else(|| {
+        Error::Config(format!(
+            "{} of {} has to be a comma-separated list",
+            section, key
+        ))
+    })
+}
+
+fn parse_allowed_highways(doc: &Yaml) -> Result<HashMap<NetworkType, HashSet<String>>, Error> {
+    let mut allowed_highways: HashMap<NetworkType, HashSet<String>> = HashMap::new();
+
+    for (key, types) in entries(doc, "allowed_highways")? {
+        let types = parse_list("allowed_highways", key, types)?
+            .into_iter()
+            .collect::<HashSet<String>>();
+
+        let network_type = parse_network_type("allowed_highways", key)?;
+        allowed_highways.insert(network_type, types);
+    }
 
-    allowed_highways
+    Ok(allowed_highways)
 }
 
-fn parse_max_speeds(doc: &Yaml) -> HashMap<String, u8> {
+// the access sections are optional, so that older config files keep working
+fn parse_access_hierarchy(doc: &Yaml) -> Result<HashMap<NetworkType, Vec<String>>, Error> {
+    let mut access_hierarchy: HashMap<NetworkType, Vec<String>> = HashMap::new();
+
+    if doc["access_hierarchy"].is_badvalue() {
+        return Ok(access_hierarchy);
+    }
+
+    for (key, tags) in entries(doc, "access_hierarchy")? {
+        let network_type = parse_network_type("access_hierarchy", key)?;
+        access_hierarchy.insert(network_type, parse_list("access_hierarchy", key, tags)?);
+    }
+
+    Ok(access_hierarchy)
+}
+
+fn parse_access_values(doc: &Yaml, key: &str) -> HashSet<String> {
+    doc[key]
+        .as_str()
+        .map(split_list)
+        .unwrap_or_default()
+        .into_iter()
+        .collect()
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty())
+        .collect()
+}
+
+// the speed of "unknown" is the fallback for all other street types
+fn parse_max_speeds(doc: &Yaml) -> Result<HashMap<String, u8>, Error> {
     let mut max_speed = HashMap::new();
-    let max_speeds_file = doc["max_speed"].as_vec().unwrap();
-    for entry in max_speeds_file {
-        for (key, speed) in entry.as_hash().unwrap() {
-            let speed = speed.as_i64().unwrap();
-            let t = key.as_str().unwrap();
+    for (t, speed) in entries(doc, "max_speed")? {
+        let speed = parse_speed(speed)
+            .ok_or_else(|| Error::Config(format!("invalid max_speed of {}", t)))?;
 
-            max_speed.insert(t.to_string(), speed as u8);
-        }
+        max_speed.insert(t.to_string(), speed);
+    }
+
+    if !max_speed.contains_key("unknown") {
+        return Err(Error::Config(
+            "max_speed has no entry for unknown".to_string(),
+        ));
     }
-    max_speed
+    Ok(max_speed)
 }
 
-fn parse_default_walking_speed(doc: &Yaml) -> u8 {
-    doc["default_walking_speed"].as_i64().unwrap() as u8
+fn parse_default_walking_speed(doc: &Yaml) -> Result<u8, Error> {
+    parse_speed(&doc["default_walking_speed"])
+        .ok_or_else(|| Error::Config("missing or invalid default_walking_speed".to_string()))
+}
+
+fn parse_speed(speed: &Yaml) -> Option<u8> {
+    speed
+        .as_i64()
+        .filter(|speed| *speed > 0 && *speed <= u8::MAX as i64)
+        .map(|speed| speed as u8)
+}
+
+// TESTS
+#[cfg(test)]
+fn config_error(config: &str) -> String {
+    create_config_from_string(config.to_string())
+        .err()
+        .unwrap()
+        .to_string()
+}
+
+#[test]
+fn should_create_default_config() {
+    assert!(create_default_config().is_ok());
+}
+
+#[test]
+fn should_reject_invalid_configs() {
+    let speeds = "max_speed:\n  - unknown: 50\ndefault_walking_speed: 5\n";
+
+    assert!(config_error("max_speed: [").starts_with("invalid config: "));
+    assert!(config_error(speeds) == "invalid config: missing section: allowed_highways");
+    assert!(
+        config_error(&format!("allowed_highways:\n  - boat: river\n{}", speeds))
+            == "invalid config: unknown network type in allowed_highways: boat"
+    );
+    assert!(
+        config_error(&format!("allowed_highways: car\n{}", speeds))
+            == "invalid config: allowed_highways has to be a list"
+    );
+    assert!(
+        config_error("allowed_highways:\n  - car: primary\nmax_speed:\n  - primary: 300\n")
+            == "invalid config: invalid max_speed of primary"
+    );
+    assert!(
+        config_error("allowed_highways:\n  - car: primary\nmax_speed:\n  - primary: 100\n")
+            == "invalid config: max_speed has no entry for unknown"
+    );
+    assert!(
+        config_error(&format!(
+            "allowed_highways:\n  - car: primary\n{}",
+            &speeds[..27]
+        )) == "invalid config: missing or invalid default_walking_speed"
+    );
 }
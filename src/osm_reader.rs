@@ -1,102 +1,419 @@
-use osmpbfreader::{Node, NodeId, OsmObj, OsmPbfReader, Way};
-use std::collections::HashMap;
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
+use osmpbfreader::{OsmObj, OsmPbfReader, Relation, Tags, Way};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
 use std::time::Instant;
 
+use clipping::{self, Clip};
+use error::Error;
+use network_type::NetworkType;
+use node_store::NodeStore;
 use osm_parse_config::OSMParseConfig;
-use std;
+use osm_xml_reader;
 
-fn filter_nodes_and_ways(
-    mut nodes: HashMap<NodeId, Node>,
-    mut ways: Vec<Way>,
-    osm_parse_config: &OSMParseConfig,
-) -> (HashMap<NodeId, Node>, Vec<Way>) {
-    let mut ways_filtered: Vec<Way> = Vec::new();
-    let mut nodes_filtered: HashMap<NodeId, Node> = HashMap::new();
-    let nodes_initially = nodes.len();
-    let ways_initially = ways.len();
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum InputFormat {
+    Pbf,
+    // plain, gzip or bzip2 compressed OSM XML
+    Xml,
+}
 
-    let now = Instant::now();
-    while !ways.is_empty() {
-        let way = ways.pop().unwrap();
-        let all_nodes_available = way
-            .nodes
-            .iter()
-            .all(|x| (nodes.contains_key(x) || nodes_filtered.contains_key(x)));
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const BZIP2_MAGIC: &[u8] = b"BZh";
+
+// guesses the format by file extension, and by the first bytes of the file otherwise
+pub fn detect_input_format(filename: &str) -> Result<InputFormat, Error> {
+    let lower = filename.to_lowercase();
+    if lower.ends_with(".pbf") {
+        return Ok(InputFormat::Pbf);
+    }
+
+    let xml_extensions = [".osm", ".xml"];
+    let is_xml = xml_extensions.iter().any(|ext| {
+        lower.ends_with(ext)
+            || lower.ends_with(&format!("{}.gz", ext))
+            || lower.ends_with(&format!("{}.bz2", ext))
+    });
+    if is_xml {
+        return Ok(InputFormat::Xml);
+    }
 
-        let is_area = way.tags.get("area").map(|x| x == "yes").unwrap_or(false);
+    let mut start = Vec::new();
+    File::open(filename)?.take(64).read_to_end(&mut start)?;
+    Ok(sniff_input_format(&start))
+}
 
-        let allowed_highway = way
-            .tags
-            .get("highway")
-            .map(|x| osm_parse_config.is_allowed(&(::NETWORK_TYPE), x))
+fn sniff_input_format(start: &[u8]) -> InputFormat {
+    let is_xml = start.starts_with(GZIP_MAGIC)
+        || start.starts_with(BZIP2_MAGIC)
+        || start
+            .iter()
+            .find(|c| !c.is_ascii_whitespace())
+            .map(|c| *c == b'<')
             .unwrap_or(false);
 
-        let way_ok = all_nodes_available && !is_area && allowed_highway;
+    if is_xml {
+        InputFormat::Xml
+    } else {
+        InputFormat::Pbf
+    }
+}
+
+fn open_xml(filename: &str) -> Result<Box<dyn BufRead>, Error> {
+    let mut file = BufReader::new(File::open(filename)?);
+    let start = file.fill_buf()?.to_vec();
 
-        if way_ok {
-            way.nodes
-                .iter()
-                .filter_map(|x| nodes.remove(x))
-                .for_each(|n| {
-                    nodes_filtered.insert(n.id, n);
-                });
+    if start.starts_with(GZIP_MAGIC) {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(file))))
+    } else if start.starts_with(BZIP2_MAGIC) {
+        Ok(Box::new(BufReader::new(MultiBzDecoder::new(file))))
+    } else {
+        Ok(Box::new(file))
+    }
+}
 
-            ways_filtered.push(way);
+// calls `f` for every object in the file; each call reads the file from the start
+fn for_each_object<F: FnMut(OsmObj)>(
+    filename: &str,
+    input_format: InputFormat,
+    mut f: F,
+) -> Result<(), Error> {
+    match input_format {
+        InputFormat::Pbf => {
+            let mut pbf = OsmPbfReader::new(File::open(filename)?);
+            for obj in pbf.par_iter() {
+                f(obj?);
+            }
+            Ok(())
         }
+        InputFormat::Xml => osm_xml_reader::for_each_object(open_xml(filename)?, f),
     }
+}
 
-    println!(
-        "filtered unnecessary nodes and ways: {}s",
-        now.elapsed().as_secs()
-    );
-    println!(
-        "#nodes now: {}/{} ({:.2}%)",
-        nodes_filtered.len(),
-        nodes_initially,
-        nodes_filtered.len() as f64 / nodes_initially as f64 * 100.0
-    );
-    println!(
+fn is_way_allowed(
+    way: &Way,
+    osm_parse_config: &OSMParseConfig,
+    network_type: &NetworkType,
+) -> bool {
+    let is_area = way.tags.get("area").map(|x| x == "yes").unwrap_or(false);
+
+    let highway = match way.tags.get("highway") {
+        Some(highway) => highway,
+        None => return false,
+    };
+    let allowed_highway = osm_parse_config.is_allowed(network_type, highway);
+
+    // e.g. highway=construction stays closed whatever its access tags say
+    let allowed = osm_parse_config.is_routable(highway)
+        && get_access(&way.tags, osm_parse_config, network_type).unwrap_or(allowed_highway);
+
+    !is_area && allowed
+}
+
+// drops ways referring to nodes that are missing in the input
+fn filter_ways(ways: Vec<Way>, nodes: &NodeStore) -> Vec<Way> {
+    let ways_initially = ways.len();
+
+    let now = Instant::now();
+    let ways_filtered: Vec<Way> = ways
+        .into_iter()
+        .filter(|way| way.nodes.iter().all(|x| nodes.contains(x)))
+        .collect();
+
+    info!("filtered incomplete ways: {}s", now.elapsed().as_secs());
+    info!(
         "#ways now: {}/{} ({:.2}%)",
         ways_filtered.len(),
         ways_initially,
         ways_filtered.len() as f64 / ways_initially as f64 * 100.0
     );
-    println!();
 
-    (nodes_filtered, ways_filtered)
+    ways_filtered
 }
 
-fn read_nodes_and_ways(file_reference: std::fs::File) -> (HashMap<NodeId, Node>, Vec<Way>) {
-    let mut pbf = OsmPbfReader::new(file_reference);
+// Some(true) if the access tags explicitly allow the network type, Some(false)
+// if they deny it, and None if they do not decide
+fn get_access(
+    tags: &Tags,
+    osm_parse_config: &OSMParseConfig,
+    network_type: &NetworkType,
+) -> Option<bool> {
+    let hierarchy = osm_parse_config.access_hierarchy(network_type);
+
+    for (i, key) in hierarchy.iter().enumerate().rev() {
+        let value = match tags.get(key.as_str()) {
+            Some(value) => value,
+            None => continue,
+        };
+
+        if osm_parse_config.is_access_denied(value) {
+            return Some(false);
+        }
+        if osm_parse_config.is_access_allowed(value) {
+            // the most general access tag can only restrict
+            return if i > 0 { Some(true) } else { None };
+        }
+    }
+
+    None
+}
 
-    let mut nodes = HashMap::new();
+// first pass: keeps only the ways relevant for the network type and the turn restrictions
+fn read_ways_and_relations(
+    filename: &str,
+    input_format: InputFormat,
+    osm_parse_config: &OSMParseConfig,
+    network_type: &NetworkType,
+) -> Result<(Vec<Way>, Vec<Relation>), Error> {
     let mut ways = Vec::new();
+    let mut relations = Vec::new();
+    let mut ways_initially = 0;
 
     let now = Instant::now();
-    for obj in pbf.par_iter().map(Result::unwrap) {
-        match obj {
-            OsmObj::Node(node) => {
-                nodes.insert(node.id, node);
-            }
-            OsmObj::Way(way) => {
+    for_each_object(filename, input_format, |obj| match obj {
+        OsmObj::Way(way) => {
+            ways_initially += 1;
+            if is_way_allowed(&way, osm_parse_config, network_type) {
                 ways.push(way);
             }
-            _ => {}
         }
-    }
+        OsmObj::Relation(relation) => {
+            // only turn restrictions are of interest, keep memory low
+            if relation.tags.contains("type", "restriction") {
+                relations.push(relation);
+            }
+        }
+        OsmObj::Node(_) => {}
+    })?;
 
-    println!("finished reading of osm data: {}s", now.elapsed().as_secs());
-    println!(
-        "data contains: {} ways, and {} nodes",
+    relations.sort_unstable_by_key(|r| r.id);
+
+    info!("finished reading of osm ways: {}s", now.elapsed().as_secs());
+    info!(
+        "#ways kept: {}/{} ({:.2}%), #restrictions: {}",
         ways.len(),
-        nodes.len()
+        ways_initially,
+        ways.len() as f64 / ways_initially as f64 * 100.0,
+        relations.len()
+    );
+
+    Ok((ways, relations))
+}
+
+// second pass: reads the coordinates of the nodes used by `ways` only
+fn read_nodes(filename: &str, input_format: InputFormat, ways: &[Way]) -> Result<NodeStore, Error> {
+    let node_ids = ways.iter().flat_map(|w| w.nodes.iter().cloned()).collect();
+    let mut nodes = NodeStore::with_ids(node_ids);
+    let mut nodes_initially: u64 = 0;
+
+    let now = Instant::now();
+    for_each_object(filename, input_format, |obj| {
+        if let OsmObj::Node(node) = obj {
+            nodes_initially += 1;
+            nodes.set(node.id, node.decimicro_lat, node.decimicro_lon);
+        }
+    })?;
+
+    info!(
+        "finished reading of osm nodes: {}s",
+        now.elapsed().as_secs()
+    );
+    info!(
+        "#nodes kept: {}/{} ({:.2}%)",
+        nodes.len(),
+        nodes_initially,
+        nodes.len() as f64 / nodes_initially as f64 * 100.0
     );
 
-    (nodes, ways)
+    Ok(nodes)
+}
+
+pub fn read_osm(
+    filename: &str,
+    config: &OSMParseConfig,
+    network_type: &NetworkType,
+    clip: Option<&Clip>,
+) -> Result<(NodeStore, Vec<Way>, Vec<Relation>), Error> {
+    let input_format = detect_input_format(filename)?;
+    info!("reading {:?} input from {}", input_format, filename);
+
+    let (ways, relations) = read_ways_and_relations(filename, input_format, config, network_type)?;
+    let mut nodes = read_nodes(filename, input_format, &ways)?;
+    let ways = match clip {
+        Some(clip) => clipping::clip_ways(ways, &mut nodes, clip),
+        None => ways,
+    };
+    let ways = filter_ways(ways, &nodes);
+
+    Ok((nodes, ways, relations))
+}
+
+// TESTS
+#[cfg(test)]
+use osm_parse_config_creator;
+
+#[cfg(test)]
+fn create_tags(tags: &[(&str, &str)]) -> Tags {
+    tags.iter()
+        .map(|(k, v)| ((*k).into(), (*v).into()))
+        .collect()
+}
+
+#[test]
+fn should_not_decide_access_without_access_tags() {
+    let config = osm_parse_config_creator::create_default_config().unwrap();
+    let tags = create_tags(&[("highway", "residential")]);
+
+    assert!(get_access(&tags, &config, &NetworkType::Car).is_none());
+}
+
+#[test]
+fn should_deny_private_and_mode_specific_no() {
+    let config = osm_parse_config_creator::create_default_config().unwrap();
+    let private = create_tags(&[("highway", "residential"), ("access", "private")]);
+    let no_foot = create_tags(&[("highway", "primary"), ("foot", "no")]);
+    let no_motorcar = create_tags(&[("highway", "primary"), ("motorcar", "no")]);
+
+    assert!(get_access(&private, &config, &NetworkType::Car) == Some(false));
+    assert!(get_access(&private, &config, &NetworkType::Pedestrian) == Some(false));
+    assert!(get_access(&no_foot, &config, &NetworkType::Pedestrian) == Some(false));
+    assert!(get_access(&no_foot, &config, &NetworkType::Car).is_none());
+    assert!(get_access(&no_motorcar, &config, &NetworkType::Car) == Some(false));
+    assert!(get_access(&no_motorcar, &config, &NetworkType::Bicycle).is_none());
+}
+
+#[test]
+fn should_let_the_most_specific_tag_decide() {
+    let config = osm_parse_config_creator::create_default_config().unwrap();
+    let private_but_foot = create_tags(&[("access", "private"), ("foot", "yes")]);
+    let no_vehicle_but_motorcar = create_tags(&[("vehicle", "no"), ("motorcar", "destination")]);
+
+    assert!(get_access(&private_but_foot, &config, &NetworkType::Pedestrian) == Some(true));
+    assert!(get_access(&private_but_foot, &config, &NetworkType::Bicycle) == Some(false));
+    assert!(get_access(&no_vehicle_but_motorcar, &config, &NetworkType::Car) == Some(true));
+    assert!(get_access(&no_vehicle_but_motorcar, &config, &NetworkType::Bicycle) == Some(false));
+}
+
+#[test]
+fn should_allow_explicitly_tagged_ways_only_for_specific_tags() {
+    let config = osm_parse_config_creator::create_default_config().unwrap();
+    let footway_bicycle = create_tags(&[("highway", "footway"), ("bicycle", "yes")]);
+    let access_yes = create_tags(&[("highway", "footway"), ("access", "yes")]);
+
+    assert!(!config.is_allowed(&NetworkType::Bicycle, "footway"));
+    assert!(get_access(&footway_bicycle, &config, &NetworkType::Bicycle) == Some(true));
+    assert!(get_access(&access_yes, &config, &NetworkType::Bicycle).is_none());
+}
+
+#[test]
+fn should_open_only_routable_highways_by_access_tags() {
+    let config = osm_parse_config_creator::create_default_config().unwrap();
+    let way = |tags: &[(&str, &str)]| Way {
+        id: osmpbfreader::WayId(1),
+        tags: create_tags(tags),
+        nodes: Vec::new(),
+    };
+
+    let construction = way(&[("highway", "construction"), ("foot", "yes")]);
+    let construction_access = way(&[("highway", "construction"), ("access", "yes")]);
+    let proposed = way(&[("highway", "proposed"), ("bicycle", "yes")]);
+    let footway_bicycle = way(&[("highway", "footway"), ("bicycle", "yes")]);
+    let cycleway_foot = way(&[("highway", "cycleway"), ("foot", "yes")]);
+
+    assert!(!is_way_allowed(
+        &construction,
+        &config,
+        &NetworkType::Pedestrian
+    ));
+    assert!(!is_way_allowed(
+        &construction_access,
+        &config,
+        &NetworkType::Car
+    ));
+    assert!(!is_way_allowed(&proposed, &config, &NetworkType::Bicycle));
+    assert!(is_way_allowed(
+        &footway_bicycle,
+        &config,
+        &NetworkType::Bicycle
+    ));
+    assert!(is_way_allowed(
+        &cycleway_foot,
+        &config,
+        &NetworkType::Pedestrian
+    ));
+}
+
+#[cfg(test)]
+const TEST_FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/grid.osm");
+
+#[test]
+fn should_detect_input_format() {
+    let format = |filename: &str| detect_input_format(filename).unwrap();
+    assert!(format("extract.osm.pbf") == InputFormat::Pbf);
+    assert!(format("extract.osm") == InputFormat::Xml);
+    assert!(format("extract.OSM.BZ2") == InputFormat::Xml);
+    assert!(format("map.xml.gz") == InputFormat::Xml);
+
+    assert!(sniff_input_format(b"  <?xml version") == InputFormat::Xml);
+    assert!(sniff_input_format(&[0x1f, 0x8b, 0x08]) == InputFormat::Xml);
+    assert!(sniff_input_format(b"BZh91AY") == InputFormat::Xml);
+    assert!(sniff_input_format(&[0x00, 0x00, 0x00, 0x0d, 0x0a]) == InputFormat::Pbf);
+}
+
+#[test]
+fn should_read_and_filter_xml() {
+    let config = osm_parse_config_creator::create_default_config().unwrap();
+
+    let (nodes, ways, relations) =
+        read_osm(TEST_FIXTURE, &config, &NetworkType::Car, None).unwrap();
+
+    // the footway, the private street and the way with a missing node are dropped
+    let way_ids: Vec<i64> = ways.iter().map(|w| w.id.0).collect();
+    assert!(way_ids == vec![100, 101, 102, 103]);
+    assert!(nodes.contains(&osmpbfreader::NodeId(5)));
+    assert!(relations.len() == 1);
+}
+
+#[test]
+fn should_read_compressed_xml() {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    let config = osm_parse_config_creator::create_default_config().unwrap();
+    let filename = std::env::temp_dir().join("osmtoroadgraph_grid_test.osm.gz");
+    let mut encoder = GzEncoder::new(File::create(&filename).unwrap(), Compression::default());
+    encoder
+        .write_all(&std::fs::read(TEST_FIXTURE).unwrap())
+        .unwrap();
+    encoder.finish().unwrap();
+
+    let (_, plain_ways, _) =
+        read_osm(TEST_FIXTURE, &config, &NetworkType::Pedestrian, None).unwrap();
+    let (_, gz_ways, _) = read_osm(
+        filename.to_str().unwrap(),
+        &config,
+        &NetworkType::Pedestrian,
+        None,
+    )
+    .unwrap();
+    std::fs::remove_file(&filename).unwrap();
+
+    assert!(!plain_ways.is_empty());
+    assert!(plain_ways == gz_ways);
 }
 
-pub fn read_osm(filename: &str, config: &OSMParseConfig) -> (HashMap<NodeId, Node>, Vec<Way>) {
-    let file_reference = std::fs::File::open(&std::path::Path::new(filename)).unwrap();
-    let (nodes, ways) = read_nodes_and_ways(file_reference);
-    filter_nodes_and_ways(nodes, ways, config)
+#[test]
+fn should_report_missing_and_undecodable_input() {
+    let config = osm_parse_config_creator::create_default_config().unwrap();
+    let filename = std::env::temp_dir().join("osmtoroadgraph_invalid_test.osm.pbf");
+    std::fs::write(&filename, b"\x00\x00\x00\x04garbage").unwrap();
+
+    let missing = read_osm("does_not_exist.pbf", &config, &NetworkType::Car, None);
+    let invalid = read_osm(filename.to_str().unwrap(), &config, &NetworkType::Car, None);
+    std::fs::remove_file(&filename).unwrap();
+
+    assert!(missing.err().unwrap().exit_code() == 3);
+    assert!(invalid.err().unwrap().exit_code() == 4);
 }
@@ -0,0 +1,276 @@
+use error::Error;
+use osmpbfreader::{Node, NodeId, OsmId, OsmObj, Ref, Relation, RelationId, Tags, Way, WayId};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::collections::HashMap;
+use std::io::BufRead;
+
+// calls `f` for every node, way and relation of an OSM XML document, in
+// document order; objects marked as deleted are skipped. Change files can not
+// be applied without the data they change and are rejected.
+pub fn for_each_object<R: BufRead, F: FnMut(OsmObj)>(input: R, mut f: F) -> Result<(), Error> {
+    let mut reader = Reader::from_reader(input);
+    reader.trim_text(true);
+
+    let mut buf = Vec::new();
+    let mut current: Option<OsmObj> = None;
+
+    loop {
+        let position = reader.buffer_position();
+        let event = reader
+            .read_event_into(&mut buf)
+            .map_err(|e| at(position, e.into()))?;
+        // start of the element, the reader is behind its closing bracket
+        let end = reader.buffer_position();
+        match event {
+            Event::Start(ref e) => {
+                let position = end - e.len() - 2;
+                if let Some(obj) = start_object(e).map_err(|e| at(position, e))? {
+                    current = Some(obj);
+                } else if let Some(ref mut obj) = current {
+                    add_child(obj, e).map_err(|e| at(position, e))?;
+                }
+            }
+            Event::Empty(ref e) => {
+                let position = end - e.len() - 3;
+                if let Some(obj) = start_object(e).map_err(|e| at(position, e))? {
+                    f(obj);
+                } else if let Some(ref mut obj) = current {
+                    add_child(obj, e).map_err(|e| at(position, e))?;
+                }
+            }
+            Event::End(ref e) => {
+                let name = e.name();
+                let name = name.as_ref();
+                if name == b"node" || name == b"way" || name == b"relation" {
+                    if let Some(obj) = current.take() {
+                        f(obj);
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(())
+}
+
+// prefixes parse errors with the byte offset of the element
+fn at(position: usize, error: Error) -> Error {
+    match error {
+        Error::Xml(e) => Error::Xml(format!("byte {}: {}", position, e)),
+        error => error,
+    }
+}
+
+fn attributes(e: &BytesStart) -> Result<HashMap<Vec<u8>, String>, Error> {
+    let mut attributes = HashMap::new();
+    for a in e.attributes() {
+        let a = a?;
+        attributes.insert(a.key.as_ref().to_vec(), a.unescape_value()?.into_owned());
+    }
+    Ok(attributes)
+}
+
+fn parse_attribute<T: std::str::FromStr>(
+    attributes: &HashMap<Vec<u8>, String>,
+    key: &[u8],
+) -> Result<T, Error> {
+    attributes
+        .get(key)
+        .and_then(|x| x.parse::<T>().ok())
+        .ok_or_else(|| {
+            Error::Xml(format!(
+                "invalid or missing attribute: {}",
+                std::string::String::from_utf8_lossy(key)
+            ))
+        })
+}
+
+fn start_object(e: &BytesStart) -> Result<Option<OsmObj>, Error> {
+    let name = e.name();
+    let name = name.as_ref();
+    if name == b"osmChange" {
+        return Err(Error::Xml(
+            "osmChange files are not supported, apply them to the data they change first"
+                .to_string(),
+        ));
+    }
+    if name != b"node" && name != b"way" && name != b"relation" {
+        return Ok(None);
+    }
+
+    let a = attributes(e)?;
+    let id: i64 = parse_attribute(&a, b"id")?;
+
+    // deleted in an editor (JOSM) or in the history of the object; its tags
+    // and members are dropped as there is no current object to add them to
+    let deleted = a.get(&b"action"[..]).map(|x| x.as_str()) == Some("delete")
+        || a.get(&b"visible"[..]).map(|x| x.as_str()) == Some("false");
+    if deleted {
+        return Ok(None);
+    }
+
+    let obj = match name {
+        b"node" => {
+            let lat: f64 = parse_attribute(&a, b"lat")?;
+            let lon: f64 = parse_attribute(&a, b"lon")?;
+            OsmObj::Node(Node {
+                id: NodeId(id),
+                tags: Tags::new(),
+                decimicro_lat: (lat * 1e7).round() as i32,
+                decimicro_lon: (lon * 1e7).round() as i32,
+            })
+        }
+        b"way" => OsmObj::Way(Way {
+            id: WayId(id),
+            tags: Tags::new(),
+            nodes: Vec::new(),
+        }),
+        _ => OsmObj::Relation(Relation {
+            id: RelationId(id),
+            tags: Tags::new(),
+            refs: Vec::new(),
+        }),
+    };
+
+    Ok(Some(obj))
+}
+
+fn add_child(obj: &mut OsmObj, e: &BytesStart) -> Result<(), Error> {
+    let a = attributes(e)?;
+
+    match (e.name().as_ref(), obj) {
+        (b"tag", OsmObj::Node(ref mut node)) => add_tag(&mut node.tags, &a),
+        (b"tag", OsmObj::Way(ref mut way)) => add_tag(&mut way.tags, &a),
+        (b"tag", OsmObj::Relation(ref mut relation)) => add_tag(&mut relation.tags, &a),
+        (b"nd", OsmObj::Way(ref mut way)) => way.nodes.push(NodeId(parse_attribute(&a, b"ref")?)),
+        (b"member", OsmObj::Relation(ref mut relation)) => {
+            let id: i64 = parse_attribute(&a, b"ref")?;
+            let member = match a.get(&b"type"[..]).map(|x| x.as_str()) {
+                Some("node") => OsmId::Node(NodeId(id)),
+                Some("way") => OsmId::Way(WayId(id)),
+                Some("relation") => OsmId::Relation(RelationId(id)),
+                _ => return Ok(()),
+            };
+            let role = a.get(&b"role"[..]).map(|x| x.as_str()).unwrap_or("");
+            relation.refs.push(Ref {
+                member,
+                role: role.into(),
+            });
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn add_tag(tags: &mut Tags, attributes: &HashMap<Vec<u8>, String>) {
+    if let (Some(k), Some(v)) = (attributes.get(&b"k"[..]), attributes.get(&b"v"[..])) {
+        tags.insert(k.as_str().into(), v.as_str().into());
+    }
+}
+
+// TESTS
+#[cfg(test)]
+const TEST_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6" generator="test">
+  <bounds minlat="0.0" minlon="0.0" maxlat="1.0" maxlon="1.0"/>
+  <node id="1" lat="0.1" lon="0.2"/>
+  <node id="2" lat="0.3" lon="-0.4">
+    <tag k="highway" v="traffic_signals"/>
+  </node>
+  <way id="10">
+    <nd ref="1"/>
+    <nd ref="2"/>
+    <tag k="highway" v="residential"/>
+    <tag k="name" v="Fish &amp; Chips Lane"/>
+  </way>
+  <relation id="100">
+    <member type="way" ref="10" role="from"/>
+    <member type="node" ref="2" role="via"/>
+    <member type="way" ref="11" role="to"/>
+    <tag k="type" v="restriction"/>
+    <tag k="restriction" v="no_left_turn"/>
+  </relation>
+</osm>
+"#;
+
+#[test]
+fn should_read_nodes_ways_and_relations() {
+    let mut objects = Vec::new();
+    for_each_object(TEST_XML.as_bytes(), |obj| objects.push(obj)).unwrap();
+
+    assert!(objects.len() == 4);
+
+    let node = objects[0].node().unwrap();
+    assert!(node.id == NodeId(1));
+    assert!(node.decimicro_lat == 1_000_000 && node.decimicro_lon == 2_000_000);
+
+    let node = objects[1].node().unwrap();
+    assert!(node.decimicro_lon == -4_000_000);
+    assert!(node.tags.contains("highway", "traffic_signals"));
+
+    let way = objects[2].way().unwrap();
+    assert!(way.id == WayId(10));
+    assert!(way.nodes == vec![NodeId(1), NodeId(2)]);
+    assert!(way.tags.contains("name", "Fish & Chips Lane"));
+
+    let relation = objects[3].relation().unwrap();
+    assert!(relation.refs.len() == 3);
+    assert!(relation.refs[1].member == OsmId::Node(NodeId(2)));
+    assert!(relation.refs[1].role == "via");
+    assert!(relation.tags.contains("restriction", "no_left_turn"));
+}
+
+#[test]
+fn should_report_invalid_xml() {
+    let error = |xml: &str| {
+        for_each_object(xml.as_bytes(), |_| {})
+            .err()
+            .unwrap()
+            .to_string()
+    };
+
+    assert!(
+        error("<osm>\n<node id=\"1\" lat=\"x\" lon=\"0\"/>\n</osm>")
+            == "could not parse OSM XML input: byte 6: invalid or missing attribute: lat"
+    );
+    assert!(error("<osm><way id=\"1\"><nd/></way></osm>").ends_with("attribute: ref"));
+    assert!(error("<osm></way>").starts_with("could not parse OSM XML input: "));
+    assert!(
+        error("<osmChange version=\"0.6\">\n<modify/>\n</osmChange>").starts_with(
+            "could not parse OSM XML input: byte 0: osmChange files are not supported"
+        )
+    );
+}
+
+#[test]
+fn should_skip_deleted_objects() {
+    let xml = r#"<osm version="0.6">
+  <node id="1" lat="0.1" lon="0.2" action="delete"/>
+  <node id="2" lat="0.3" lon="0.4" action="modify"/>
+  <way id="10" visible="false">
+    <nd ref="1"/>
+    <nd ref="2"/>
+    <tag k="highway" v="residential"/>
+  </way>
+  <way id="11" visible="true">
+    <nd ref="2"/>
+    <nd ref="3"/>
+  </way>
+</osm>
+"#;
+
+    let mut objects = Vec::new();
+    for_each_object(xml.as_bytes(), |obj| objects.push(obj)).unwrap();
+
+    assert!(objects.len() == 2);
+    assert!(objects[0].node().unwrap().id == NodeId(2));
+    let way = objects[1].way().unwrap();
+    assert!(way.id == WayId(11));
+    assert!(way.nodes == vec![NodeId(2), NodeId(3)]);
+    assert!(way.tags.is_empty());
+}
@@ -1,28 +1,89 @@
+use contraction::ContractedEdgeData;
+use error::Error;
 use graph;
+use osmpbfreader::{NodeId, WayId};
 
-use graph::GraphEdgeDescription;
-use graph_data::{EdgeDataDescription, NodeDataDescription};
+use graph::{GraphEdgeDescription, GraphNodeId};
+use graph_data::{EdgeData, EdgeDataDescription, NodeData, NodeDataDescription};
+use restrictions::TurnRestriction;
 
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
+use std::path::Path;
+use util;
+use util::invalid_data;
 
-const HEADER: &str = "# Road Graph File v.0.4\
-                      # number of nodes\
-                      # number of edges \
-                      # node_properties \
-                      # ... \
-                      # edge_properties \
-                      # ...";
+const RESTRICTIONS_HEADER: &str = "# turn restrictions: (no|only) from_edge [via_edge ...] to_edge";
+
+// header as written up to v.0.4: the line continuations join all comment
+// lines into one and the counts it announces are missing
+const LEGACY_HEADER: &str = "# Road Graph File v.0.4\
+                             # number of nodes\
+                             # number of edges \
+                             # node_properties \
+                             # ... \
+                             # edge_properties \
+                             # ...";
+
+const HEADER: &str = "# Road Graph File v.0.5
+# number of nodes
+# number of edges
+# node_properties
+# lat lon
+# ...
+# edge_properties
+# s t street_type max_speed bidirectional length travel_time
+# length in meters, travel_time in seconds at max_speed
+# ...";
+
+// layout of the text graph file
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum FormatVersion {
+    // v.0.4: single header line, no node and edge counts
+    Legacy,
+    // v.0.5: one header line per comment, followed by the node and edge counts
+    Corrected,
+}
+
+pub fn get_format_version(version: &str) -> Result<FormatVersion, Error> {
+    match version {
+        "0.4" => Ok(FormatVersion::Legacy),
+        "0.5" => Ok(FormatVersion::Corrected),
+        _ => Err(Error::Argument(format!(
+            "did not recognize format version: {}",
+            version
+        ))),
+    }
+}
 
 pub fn write<TN: NodeDataDescription, TE: EdgeDataDescription>(
     g: &graph::Graph<TN, TE>,
     filename: String,
+    version: FormatVersion,
 ) -> Result<(), io::Error> {
-    let mut f = File::create(filename)?;
+    let mut f = io::BufWriter::new(File::create(filename)?);
+    write_graph(g, &mut f, version)?;
+    f.flush()
+}
 
-    f.write_all(HEADER.as_bytes())?;
-    f.write_all(b"\n")?;
+pub fn write_graph<TN: NodeDataDescription, TE: EdgeDataDescription, W: Write>(
+    g: &graph::Graph<TN, TE>,
+    f: &mut W,
+    version: FormatVersion,
+) -> Result<(), io::Error> {
+    match version {
+        FormatVersion::Legacy => {
+            f.write_all(LEGACY_HEADER.as_bytes())?;
+            f.write_all(b"\n")?;
+        }
+        FormatVersion::Corrected => {
+            f.write_all(HEADER.as_bytes())?;
+            f.write_all(b"\n")?;
+            writeln!(f, "{}", g.nodes.len())?;
+            writeln!(f, "{}", g.edges.len())?;
+        }
+    }
 
     for node in &g.nodes {
         f.write_all(node.data.description().as_bytes())?;
@@ -31,6 +92,11 @@ pub fn write<TN: NodeDataDescription, TE: EdgeDataDescription>(
 
     for edge in &g.edges {
         f.write_all(edge.description().as_bytes())?;
+        if version == FormatVersion::Corrected {
+            if let Some((length, travel_time)) = edge.data.length_and_travel_time() {
+                write!(f, " {:.3} {:.3}", length, travel_time)?;
+            }
+        }
         f.write_all(b"\n")?;
     }
 
@@ -50,3 +116,686 @@ pub fn write_names<TN: NodeDataDescription, TE: EdgeDataDescription>(
 
     Ok(())
 }
+
+// one line per node with its OSM node id, empty if unknown
+pub fn write_node_osm_ids<TN: NodeDataDescription, TE: EdgeDataDescription>(
+    g: &graph::Graph<TN, TE>,
+    filename: String,
+) -> Result<(), io::Error> {
+    let mut f = io::BufWriter::new(File::create(filename)?);
+
+    for node in &g.nodes {
+        if let Some(id) = node.data.osm_id() {
+            f.write_all(id.to_string().as_bytes())?;
+        }
+        f.write_all(b"\n")?;
+    }
+
+    f.flush()
+}
+
+// one line per edge with the id of the OSM way it originates from, empty if unknown
+pub fn write_edge_osm_ids<TN: NodeDataDescription, TE: EdgeDataDescription>(
+    g: &graph::Graph<TN, TE>,
+    filename: String,
+) -> Result<(), io::Error> {
+    let mut f = io::BufWriter::new(File::create(filename)?);
+
+    for edge in &g.edges {
+        if let Some(id) = edge.data.osm_id() {
+            f.write_all(id.to_string().as_bytes())?;
+        }
+        f.write_all(b"\n")?;
+    }
+
+    f.flush()
+}
+
+pub fn write_original_nodes<TN: NodeDataDescription>(
+    g: &graph::Graph<TN, ContractedEdgeData>,
+    filename: String,
+) -> Result<(), io::Error> {
+    let mut f = File::create(filename)?;
+
+    for edge in &g.edges {
+        let ids = edge
+            .data
+            .original_nodes
+            .iter()
+            .map(|x| x.0.to_string())
+            .collect::<Vec<String>>()
+            .join(" ");
+        f.write_all(ids.as_bytes())?;
+        f.write_all(b"\n")?;
+    }
+
+    Ok(())
+}
+
+// one line per contracted edge with the OSM way ids of the merged edges
+pub fn write_original_ways<TN: NodeDataDescription>(
+    g: &graph::Graph<TN, ContractedEdgeData>,
+    filename: String,
+) -> Result<(), io::Error> {
+    let mut f = File::create(filename)?;
+
+    for edge in &g.edges {
+        let ids = edge
+            .data
+            .original_ways
+            .iter()
+            .map(|x| x.0.to_string())
+            .collect::<Vec<String>>()
+            .join(" ");
+        f.write_all(ids.as_bytes())?;
+        f.write_all(b"\n")?;
+    }
+
+    Ok(())
+}
+
+pub fn write_restrictions(
+    restrictions: &[TurnRestriction],
+    filename: String,
+) -> Result<(), io::Error> {
+    let mut f = File::create(filename)?;
+
+    f.write_all(RESTRICTIONS_HEADER.as_bytes())?;
+    f.write_all(b"\n")?;
+
+    for restriction in restrictions {
+        f.write_all(restriction.description().as_bytes())?;
+        f.write_all(b"\n")?;
+    }
+
+    Ok(())
+}
+
+const NODES_CSV_HEADER: &str = "id,osm_id,lat,lon";
+
+const EDGES_CSV_HEADER: &str = "id,source,target,length,street_type,max_speed,bidirectional,name";
+
+// writes a node and an edge table with a header row each, quoted as in RFC 4180
+pub fn write_csv<TN: NodeDataDescription, TE: EdgeDataDescription>(
+    g: &graph::Graph<TN, TE>,
+    nodes_filename: String,
+    edges_filename: String,
+) -> Result<(), io::Error> {
+    let mut f = io::BufWriter::new(File::create(nodes_filename)?);
+    write_nodes_csv(g, &mut f)?;
+    f.flush()?;
+
+    let mut f = io::BufWriter::new(File::create(edges_filename)?);
+    write_edges_csv(g, &mut f)?;
+    f.flush()
+}
+
+pub fn write_nodes_csv<TN: NodeDataDescription, TE: EdgeDataDescription, W: Write>(
+    g: &graph::Graph<TN, TE>,
+    f: &mut W,
+) -> Result<(), io::Error> {
+    f.write_all(NODES_CSV_HEADER.as_bytes())?;
+    f.write_all(b"\n")?;
+
+    for node in &g.nodes {
+        let mut fields = vec![
+            node.id.0.to_string(),
+            node.data.osm_id().map_or(String::new(), |x| x.to_string()),
+        ];
+        fields.extend(node.data.csv_fields());
+        write_csv_record(f, &fields)?;
+    }
+
+    Ok(())
+}
+
+pub fn write_edges_csv<TN: NodeDataDescription, TE: EdgeDataDescription, W: Write>(
+    g: &graph::Graph<TN, TE>,
+    f: &mut W,
+) -> Result<(), io::Error> {
+    f.write_all(EDGES_CSV_HEADER.as_bytes())?;
+    f.write_all(b"\n")?;
+
+    for edge in &g.edges {
+        let mut fields = vec![
+            edge.id.0.to_string(),
+            edge.s.0.to_string(),
+            edge.t.0.to_string(),
+        ];
+        fields.extend(edge.data.csv_fields());
+        fields.push(edge.data.name());
+        write_csv_record(f, &fields)?;
+    }
+
+    Ok(())
+}
+
+fn write_csv_record<W: Write>(f: &mut W, fields: &[String]) -> Result<(), io::Error> {
+    let record = fields
+        .iter()
+        .map(|x| quote_csv_field(x))
+        .collect::<Vec<String>>()
+        .join(",");
+    f.write_all(record.as_bytes())?;
+    f.write_all(b"\n")
+}
+
+// quotes fields containing separators, quotes or line breaks; quotes are doubled
+fn quote_csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// reads a graph written by `write` in either format version together with
+// its `_names` file; OSM ids
+// are taken from the `_node_osm_ids` and `_edge_osm_ids` files if present
+// and are 0 otherwise. Edge geometries only consist of the two end points, and
+// so does the length of edges written without one.
+pub fn read(filename: &str) -> Result<graph::Graph<NodeData, EdgeData>, io::Error> {
+    let graph_file = io::BufReader::new(File::open(filename)?);
+    let names_file = io::BufReader::new(File::open(format!("{}_names", filename))?);
+    let mut g = read_graph(graph_file, names_file)?;
+
+    let node_ids_filename = format!("{}_node_osm_ids", filename);
+    if Path::new(&node_ids_filename).exists() {
+        let ids = read_osm_ids(io::BufReader::new(File::open(node_ids_filename)?))?;
+        if ids.len() != g.nodes.len() {
+            return Err(invalid_data(format!(
+                "expected {} node osm ids, found {}",
+                g.nodes.len(),
+                ids.len()
+            )));
+        }
+        for (node, id) in g.nodes.iter_mut().zip(ids) {
+            node.data.osm_id = NodeId(id.unwrap_or(0));
+        }
+    }
+
+    let edge_ids_filename = format!("{}_edge_osm_ids", filename);
+    if Path::new(&edge_ids_filename).exists() {
+        let ids = read_osm_ids(io::BufReader::new(File::open(edge_ids_filename)?))?;
+        if ids.len() != g.edges.len() {
+            return Err(invalid_data(format!(
+                "expected {} edge osm ids, found {}",
+                g.edges.len(),
+                ids.len()
+            )));
+        }
+        for (edge, id) in g.edges.iter_mut().zip(ids) {
+            edge.data.osm_way_id = WayId(id.unwrap_or(0));
+        }
+    }
+
+    Ok(g)
+}
+
+pub fn read_graph<R: BufRead, S: BufRead>(
+    graph_file: R,
+    names_file: S,
+) -> Result<graph::Graph<NodeData, EdgeData>, io::Error> {
+    let mut g = graph::Graph {
+        nodes: Vec::new(),
+        edges: Vec::new(),
+    };
+    let mut names = names_file.lines();
+    // only present in the corrected format
+    let mut counts = Vec::new();
+
+    for (i, line) in graph_file.lines().enumerate() {
+        let line = line?;
+        let line_number = i + 1;
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.len() {
+            1 if counts.len() < 2 && g.nodes.is_empty() => {
+                let count: usize = parse_field(&fields, 0, "count", line_number)?;
+                counts.push(count);
+            }
+            2 if g.edges.is_empty() => {
+                let lat = parse_field(&fields, 0, "latitude", line_number)?;
+                let lon = parse_field(&fields, 1, "longitude", line_number)?;
+                g.add_node(NodeData {
+                    osm_id: NodeId(0),
+                    lat,
+                    lon,
+                });
+            }
+            // v.0.4 edges have no length, it is taken from the end points
+            5 | 7 => {
+                let s: u32 = parse_field(&fields, 0, "source", line_number)?;
+                let t: u32 = parse_field(&fields, 1, "target", line_number)?;
+                let max_speed = parse_field(&fields, 3, "max_speed", line_number)?;
+                let length: Option<f64> = if fields.len() == 7 {
+                    // the travel time follows from length and max_speed
+                    parse_field::<f64>(&fields, 6, "travel_time", line_number)?;
+                    Some(parse_field(&fields, 5, "length", line_number)?)
+                } else {
+                    None
+                };
+                let bidirectional = match fields[4] {
+                    "1" => true,
+                    "0" => false,
+                    x => {
+                        return Err(invalid_line(
+                            line_number,
+                            format!("invalid bidirectional flag: {}", x),
+                        ))
+                    }
+                };
+                if s as usize >= g.nodes.len() || t as usize >= g.nodes.len() {
+                    return Err(invalid_line(
+                        line_number,
+                        format!("edge {} {} refers to an unknown node", s, t),
+                    ));
+                }
+                let name = match names.next() {
+                    Some(name) => name?,
+                    None => {
+                        return Err(invalid_line(
+                            line_number,
+                            "no street name left for this edge".to_string(),
+                        ))
+                    }
+                };
+
+                let s = GraphNodeId(s);
+                let t = GraphNodeId(t);
+                let geometry = vec![
+                    (
+                        g.nodes[s.0 as usize].data.lat,
+                        g.nodes[s.0 as usize].data.lon,
+                    ),
+                    (
+                        g.nodes[t.0 as usize].data.lat,
+                        g.nodes[t.0 as usize].data.lon,
+                    ),
+                ];
+                let data = EdgeData {
+                    osm_way_id: WayId(0),
+                    osm_way_piece: 0,
+                    name,
+                    length: length
+                        .unwrap_or_else(|| util::haversine_distance(geometry[0], geometry[1])),
+                    street_type: fields[2].to_string(),
+                    max_speed,
+                    bidirectional,
+                    geometry,
+                };
+                g.add_edge(s, t, data, bidirectional);
+            }
+            n => {
+                return Err(invalid_line(
+                    line_number,
+                    format!("expected a node or an edge, found {} fields", n),
+                ))
+            }
+        }
+    }
+
+    if !counts.is_empty() && counts != [g.nodes.len(), g.edges.len()] {
+        return Err(invalid_data(format!(
+            "header announces {:?} nodes and edges, found {} and {}",
+            counts,
+            g.nodes.len(),
+            g.edges.len()
+        )));
+    }
+
+    if names.next().is_some() {
+        return Err(invalid_data(format!(
+            "more street names than the {} edges of the graph",
+            g.edges.len()
+        )));
+    }
+
+    Ok(g)
+}
+
+// one optional id per line, as written by `write_node_osm_ids` and `write_edge_osm_ids`
+fn read_osm_ids<R: BufRead>(f: R) -> Result<Vec<Option<i64>>, io::Error> {
+    f.lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let line = line?;
+            if line.is_empty() {
+                return Ok(None);
+            }
+            line.parse::<i64>()
+                .map(Some)
+                .map_err(|_| invalid_line(i + 1, format!("invalid osm id: {}", line)))
+        })
+        .collect()
+}
+
+fn parse_field<T: std::str::FromStr>(
+    fields: &[&str],
+    i: usize,
+    name: &str,
+    line_number: usize,
+) -> Result<T, io::Error> {
+    fields[i]
+        .parse::<T>()
+        .map_err(|_| invalid_line(line_number, format!("invalid {}: {}", name, fields[i])))
+}
+
+fn invalid_line(line_number: usize, message: String) -> io::Error {
+    invalid_data(format!("line {}: {}", line_number, message))
+}
+
+// TESTS
+#[cfg(test)]
+fn create_graph() -> graph::Graph<NodeData, EdgeData> {
+    let mut g = graph::Graph {
+        nodes: Vec::new(),
+        edges: Vec::new(),
+    };
+    for (i, (lat, lon)) in [(52.5, 13.4), (52.501, 13.401), (52.502, 13.4)]
+        .iter()
+        .enumerate()
+    {
+        g.add_node(NodeData {
+            osm_id: NodeId(10 + i as i64),
+            lat: *lat,
+            lon: *lon,
+        });
+    }
+    let edges = [(0, 1, true, "Main Street"), (2, 1, false, "")];
+    for (s, t, bidirectional, name) in edges.iter() {
+        let data = EdgeData {
+            osm_way_id: WayId(100),
+            osm_way_piece: 0,
+            name: name.to_string(),
+            length: 131.25,
+            street_type: "residential".to_string(),
+            max_speed: 30,
+            bidirectional: *bidirectional,
+            geometry: Vec::new(),
+        };
+        g.add_edge(GraphNodeId(*s), GraphNodeId(*t), data, *bidirectional);
+    }
+    g
+}
+
+#[test]
+fn should_write_header_and_counts() {
+    let g = create_graph();
+
+    let mut legacy = Vec::new();
+    write_graph(&g, &mut legacy, FormatVersion::Legacy).unwrap();
+    let legacy = String::from_utf8(legacy).unwrap();
+    let lines: Vec<&str> = legacy.lines().collect();
+    assert!(lines.len() == 1 + 3 + 2);
+    assert!(lines[0].starts_with("# Road Graph File v.0.4#"));
+    assert!(lines[1] == "52.500000 13.400000");
+    assert!(lines[4] == "0 1 residential 30 1");
+
+    let mut corrected = Vec::new();
+    write_graph(&g, &mut corrected, FormatVersion::Corrected).unwrap();
+    let corrected = String::from_utf8(corrected).unwrap();
+    let lines: Vec<&str> = corrected.lines().collect();
+    assert!(lines.len() == 10 + 2 + 3 + 2);
+    assert!(lines[..10].iter().all(|l| l.starts_with("# ")));
+    assert!(lines[0] == "# Road Graph File v.0.5");
+    assert!(lines[7] == "# s t street_type max_speed bidirectional length travel_time");
+    assert!(lines[10] == "3");
+    assert!(lines[11] == "2");
+    assert!(lines[12] == "52.500000 13.400000");
+    assert!(lines[15] == "0 1 residential 30 1 131.250 15.750");
+}
+
+#[test]
+fn should_read_written_graph() {
+    for version in [FormatVersion::Legacy, FormatVersion::Corrected].iter() {
+        should_read_written_graph_in_version(*version);
+    }
+}
+
+#[cfg(test)]
+fn should_read_written_graph_in_version(version: FormatVersion) {
+    let g = create_graph();
+    let mut graph_file = Vec::new();
+    write_graph(&g, &mut graph_file, version).unwrap();
+    let names_file = "Main Street\n\n";
+
+    let read = read_graph(graph_file.as_slice(), names_file.as_bytes()).unwrap();
+
+    assert!(read.nodes.len() == 3);
+    assert!(read.edges.len() == 2);
+    assert!((read.nodes[1].data.lat - 52.501).abs() < 1e-6);
+    assert!(read.edges[0].data.name == "Main Street");
+    assert!(read.edges[1].data.name.is_empty());
+    assert!(read.edges[1].s == GraphNodeId(2) && read.edges[1].t == GraphNodeId(1));
+    assert!(read.edges[0].data.bidirectional && !read.edges[1].data.bidirectional);
+    if version == FormatVersion::Corrected {
+        assert!(read.edges[0].data.length == 131.25);
+    }
+    assert!(read.edges[0].data.max_speed == 30);
+    assert!(read.nodes[1].get_edges() == g.nodes[1].get_edges());
+
+    let mut written_again = Vec::new();
+    write_graph(&read, &mut written_again, version).unwrap();
+    assert!(written_again == graph_file);
+}
+
+#[cfg(test)]
+const BASELINE_FIXTURE: &str =
+    concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/baseline.pypgr");
+
+#[test]
+fn should_read_files_written_before_the_length_column() {
+    let read = read(BASELINE_FIXTURE).unwrap();
+
+    assert!(read.nodes.len() == 3);
+    assert!(read.edges.len() == 2);
+    assert!(read.edges[0].data.name == "Main Street");
+    assert!(read.edges[0].data.street_type == "residential");
+    assert!(read.edges[0].data.max_speed == 30);
+    assert!(read.edges[0].data.bidirectional && !read.edges[1].data.bidirectional);
+    assert!(read.edges[1].s == GraphNodeId(2) && read.edges[1].t == GraphNodeId(1));
+    let length = util::haversine_distance((52.502, 13.4), (52.501, 13.401));
+    assert!((read.edges[1].data.length - length).abs() < 1e-6);
+
+    // written again in the current version it is read back unchanged
+    let mut graph_file = Vec::new();
+    write_graph(&read, &mut graph_file, FormatVersion::Corrected).unwrap();
+    let read_again = read_graph(graph_file.as_slice(), "Main Street\n\n".as_bytes()).unwrap();
+    assert!(read_again.edges.len() == 2);
+    assert!((read_again.edges[1].data.length - length).abs() < 1e-3);
+}
+
+#[test]
+fn should_write_legacy_files_as_before_this_series() {
+    let baseline = std::fs::read(BASELINE_FIXTURE).unwrap();
+
+    let mut graph_file = Vec::new();
+    write_graph(&create_graph(), &mut graph_file, FormatVersion::Legacy).unwrap();
+    assert!(graph_file == baseline);
+
+    let mut written_again = Vec::new();
+    write_graph(
+        &read(BASELINE_FIXTURE).unwrap(),
+        &mut written_again,
+        FormatVersion::Legacy,
+    )
+    .unwrap();
+    assert!(written_again == baseline);
+}
+
+#[test]
+fn should_write_and_read_osm_ids() {
+    let mut g = create_graph();
+    g.edges[1].data.osm_way_id = WayId(-7);
+    let filename = std::env::temp_dir().join("osmtoroadgraph_osm_ids_test.pycgr");
+    let filename = filename.to_str().unwrap().to_string();
+    let node_ids_filename = format!("{}_node_osm_ids", filename);
+    let edge_ids_filename = format!("{}_edge_osm_ids", filename);
+
+    write(&g, filename.clone(), FormatVersion::Corrected).unwrap();
+    write_names(&g, format!("{}_names", filename)).unwrap();
+    write_node_osm_ids(&g, node_ids_filename.clone()).unwrap();
+    write_edge_osm_ids(&g, edge_ids_filename.clone()).unwrap();
+    let node_ids = std::fs::read_to_string(&node_ids_filename).unwrap();
+    let edge_ids = std::fs::read_to_string(&edge_ids_filename).unwrap();
+    let read = read(&filename).unwrap();
+    for suffix in ["", "_names", "_node_osm_ids", "_edge_osm_ids"].iter() {
+        std::fs::remove_file(format!("{}{}", filename, suffix)).unwrap();
+    }
+
+    assert!(node_ids == "10\n11\n12\n");
+    assert!(edge_ids == "100\n-7\n");
+    assert!(read.nodes[2].data.osm_id == NodeId(12));
+    assert!(read.edges[0].data.osm_way_id == WayId(100));
+    assert!(read.edges[1].data.osm_way_id == WayId(-7));
+}
+
+#[test]
+fn should_quote_csv_fields() {
+    assert!(quote_csv_field("Main Street") == "Main Street");
+    assert!(quote_csv_field("Rue de l'Église, Nord") == "\"Rue de l'Église, Nord\"");
+    assert!(quote_csv_field("The \"Strip\"") == "\"The \"\"Strip\"\"\"");
+    assert!(quote_csv_field("a\nb") == "\"a\nb\"");
+}
+
+#[test]
+fn should_write_csv_tables() {
+    let mut g = create_graph();
+    g.edges[1].data.name = "Broadway, \"Upper\"".to_string();
+
+    let mut nodes = Vec::new();
+    write_nodes_csv(&g, &mut nodes).unwrap();
+    let nodes = String::from_utf8(nodes).unwrap();
+    let lines: Vec<&str> = nodes.lines().collect();
+    assert!(lines.len() == 4);
+    assert!(lines[0] == "id,osm_id,lat,lon");
+    assert!(lines[2] == "1,11,52.5010000,13.4010000");
+
+    let mut edges = Vec::new();
+    write_edges_csv(&g, &mut edges).unwrap();
+    let edges = String::from_utf8(edges).unwrap();
+    let lines: Vec<&str> = edges.lines().collect();
+    assert!(lines.len() == 3);
+    assert!(lines[0] == "id,source,target,length,street_type,max_speed,bidirectional,name");
+    assert!(lines[1] == "0,0,1,131.250,residential,30,true,Main Street");
+    assert!(lines[2] == "1,2,1,131.250,residential,30,false,\"Broadway, \"\"Upper\"\"\"");
+}
+
+#[cfg(test)]
+struct Unlocated;
+
+#[cfg(test)]
+impl NodeDataDescription for Unlocated {
+    fn description(&self) -> String {
+        "".to_string()
+    }
+}
+
+#[cfg(test)]
+impl EdgeDataDescription for Unlocated {
+    fn description(&self) -> String {
+        "".to_string()
+    }
+}
+
+#[test]
+fn should_keep_csv_columns_for_data_without_fields() {
+    let mut g: graph::Graph<Unlocated, Unlocated> = graph::Graph {
+        nodes: Vec::new(),
+        edges: Vec::new(),
+    };
+    let s = g.add_node(Unlocated);
+    let t = g.add_node(Unlocated);
+    g.add_edge(s, t, Unlocated, false);
+
+    let mut nodes = Vec::new();
+    write_nodes_csv(&g, &mut nodes).unwrap();
+    assert!(String::from_utf8(nodes).unwrap() == "id,osm_id,lat,lon\n0,,,\n1,,,\n");
+
+    let mut edges = Vec::new();
+    write_edges_csv(&g, &mut edges).unwrap();
+    assert!(
+        String::from_utf8(edges).unwrap()
+            == "id,source,target,length,street_type,max_speed,bidirectional,name\n0,0,1,,,,,\n"
+    );
+}
+
+#[test]
+fn should_report_malformed_lines() {
+    let error = |graph: &str, names: &str| {
+        read_graph(graph.as_bytes(), names.as_bytes())
+            .err()
+            .unwrap()
+            .to_string()
+    };
+
+    let nodes = "# header\n1.0 2.0\n3.0 4.0\n";
+    assert!(
+        error(&format!("{}0 1 residential 30 1 x 1.0\n", nodes), "a\n")
+            == "line 4: invalid length: x"
+    );
+    assert!(
+        error(&format!("{}0 5 residential 30 1 1.0 0.1\n", nodes), "a\n")
+            .starts_with("line 4: edge 0 5 refers to an unknown node")
+    );
+    assert!(
+        error(&format!("{}0 1 residential 30 2 1.0 0.1\n", nodes), "a\n")
+            == "line 4: invalid bidirectional flag: 2"
+    );
+    assert!(
+        error(&format!("{}0 1 residential 30\n", nodes), "a\n")
+            == "line 4: expected a node or an edge, found 4 fields"
+    );
+    assert!(
+        error(&format!("{}0 1 residential x 1\n", nodes), "a\n") == "line 4: invalid max_speed: x"
+    );
+    assert!(
+        error(&format!("{}0 1 residential 30 1 1.0 0.1\n", nodes), "")
+            == "line 4: no street name left for this edge"
+    );
+    assert!(error(
+        &format!("{}0 1 residential 30 1 1.0 0.1\n", nodes),
+        "a\nb\n"
+    )
+    .starts_with("more street names"));
+}
+
+#[test]
+fn should_expand_contracted_binary_graph_with_original_ids() {
+    use binary;
+    use contraction;
+
+    let mut g = create_graph();
+    g.edges[1].data.name = "Main Street".to_string();
+    g.edges[1].data.bidirectional = true;
+    g.edges[1].data.osm_way_id = WayId(101);
+    let c = contraction::contract(&g);
+
+    let mut buffer = Vec::new();
+    binary::write_graph(&c, &mut buffer).unwrap();
+    let read = binary::read_graph(&mut buffer.as_slice()).unwrap();
+
+    let prefix = std::env::temp_dir().join("osmtoroadgraph_original_ids_test.pycgrc");
+    let prefix = prefix.to_str().unwrap();
+    write_original_nodes(&c, format!("{}_original_nodes", prefix)).unwrap();
+    write_original_ways(&c, format!("{}_original_ways", prefix)).unwrap();
+    let nodes = std::fs::read_to_string(format!("{}_original_nodes", prefix)).unwrap();
+    let ways = std::fs::read_to_string(format!("{}_original_ways", prefix)).unwrap();
+    std::fs::remove_file(format!("{}_original_nodes", prefix)).unwrap();
+    std::fs::remove_file(format!("{}_original_ways", prefix)).unwrap();
+
+    assert!(read.edges.len() == 1);
+    assert!(nodes == "0 1 2\n");
+    assert!(ways == "100 101\n");
+
+    // the end points of the expanded edge are those of the binary edge
+    let edge = &read.edges[0];
+    let same =
+        |a: &NodeData, b: &NodeData| (a.lat - b.lat).abs() < 1e-7 && (a.lon - b.lon).abs() < 1e-7;
+    assert!(same(&read.nodes[edge.s.0 as usize].data, &g.nodes[0].data));
+    assert!(same(&read.nodes[edge.t.0 as usize].data, &g.nodes[2].data));
+}
@@ -0,0 +1,534 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Instant;
+
+use graph::{Graph, GraphEdgeId, GraphNodeId};
+use graph_data::{EdgeData, NodeData};
+use network_type::NetworkType;
+use osmpbfreader::{NodeId, OsmId, Relation, RelationId, Tags, WayId};
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum RestrictionKind {
+    No,
+    Only,
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum Via {
+    Node(NodeId),
+    Ways(Vec<WayId>),
+}
+
+// a turn restriction as given by an OSM relation
+#[derive(Debug)]
+pub struct OsmRestriction {
+    pub id: RelationId,
+    pub kind: RestrictionKind,
+    pub from: WayId,
+    pub via: Via,
+    pub to: WayId,
+}
+
+// a turn restriction on the graph: the edge sequence from, [via, ...], to
+pub struct TurnRestriction {
+    pub kind: RestrictionKind,
+    pub edges: Vec<GraphEdgeId>,
+}
+
+impl TurnRestriction {
+    pub fn description(&self) -> String {
+        let kind = match self.kind {
+            RestrictionKind::No => "no",
+            RestrictionKind::Only => "only",
+        };
+        let edges = self
+            .edges
+            .iter()
+            .map(|e| e.0.to_string())
+            .collect::<Vec<String>>()
+            .join(" ");
+        format!("{} {}", kind, edges)
+    }
+}
+
+pub fn parse_restrictions(
+    relations: &[Relation],
+    network_type: &NetworkType,
+) -> Vec<OsmRestriction> {
+    relations
+        .iter()
+        .filter_map(|r| parse_restriction(r, network_type))
+        .collect()
+}
+
+fn parse_restriction(relation: &Relation, network_type: &NetworkType) -> Option<OsmRestriction> {
+    let kind = restriction_kind(&relation.tags, network_type)?;
+
+    let mut from = Vec::new();
+    let mut to = Vec::new();
+    let mut via_nodes = Vec::new();
+    let mut via_ways = Vec::new();
+
+    for r in &relation.refs {
+        match (r.role.as_str(), r.member) {
+            ("from", OsmId::Way(id)) => from.push(id),
+            ("to", OsmId::Way(id)) => to.push(id),
+            ("via", OsmId::Node(id)) => via_nodes.push(id),
+            ("via", OsmId::Way(id)) => via_ways.push(id),
+            _ => {}
+        }
+    }
+
+    if from.len() != 1 || to.len() != 1 {
+        return None;
+    }
+
+    let via = match (via_nodes.len(), via_ways.len()) {
+        (1, 0) => Via::Node(via_nodes[0]),
+        (0, n) if n > 0 => Via::Ways(via_ways),
+        _ => return None,
+    };
+
+    Some(OsmRestriction {
+        id: relation.id,
+        kind,
+        from: from[0],
+        via,
+        to: to[0],
+    })
+}
+
+fn restriction_kind(tags: &Tags, network_type: &NetworkType) -> Option<RestrictionKind> {
+    let modes: &[&str] = match network_type {
+        NetworkType::Car => &["motorcar", "motor_vehicle", "vehicle"],
+        NetworkType::Bicycle => &["bicycle", "vehicle"],
+        NetworkType::Pedestrian => return None,
+    };
+
+    if !tags.contains("type", "restriction") {
+        return None;
+    }
+
+    let exempt = tags
+        .get("except")
+        .map(|x| x.split(';').any(|e| modes.contains(&e.trim())))
+        .unwrap_or(false);
+    if exempt {
+        return None;
+    }
+
+    let value = modes
+        .iter()
+        .filter_map(|m| tags.get(format!("restriction:{}", m).as_str()))
+        .next()
+        .or_else(|| tags.get("restriction"))?;
+
+    if value.starts_with("no_") {
+        Some(RestrictionKind::No)
+    } else if value.starts_with("only_") {
+        Some(RestrictionKind::Only)
+    } else {
+        None
+    }
+}
+
+// maps restrictions onto the edges of `g`; restrictions referring to parts
+// that are not in the graph are dropped
+pub fn map_restrictions(
+    restrictions: &[OsmRestriction],
+    g: &Graph<NodeData, EdgeData>,
+) -> Vec<TurnRestriction> {
+    let now = Instant::now();
+
+    let mut node_map: HashMap<NodeId, GraphNodeId> = HashMap::new();
+    for node in &g.nodes {
+        node_map.insert(node.data.osm_id, node.id);
+    }
+
+    let mut way_edges: HashMap<WayId, Vec<GraphEdgeId>> = HashMap::new();
+    for edge in &g.edges {
+        way_edges
+            .entry(edge.data.osm_way_id)
+            .or_default()
+            .push(edge.id);
+    }
+
+    let mut result = Vec::new();
+    for restriction in restrictions {
+        let from = way_edges.get(&restriction.from).map_or(&[][..], |x| &x[..]);
+        let to = way_edges.get(&restriction.to).map_or(&[][..], |x| &x[..]);
+
+        match restriction.via {
+            Via::Node(ref via) => {
+                let via = match node_map.get(via) {
+                    Some(via) => *via,
+                    None => continue,
+                };
+                let f = via_node_edge(g, from, via, true);
+                // a U-turn restriction only forbids turning back onto the same edge
+                let t = if restriction.from == restriction.to {
+                    f.filter(|f| can_leave(g, *f, via))
+                } else {
+                    via_node_edge(g, to, via, false)
+                };
+                if let (Some(f), Some(t)) = (f, t) {
+                    result.push(TurnRestriction {
+                        kind: restriction.kind,
+                        edges: vec![f, t],
+                    });
+                }
+            }
+            Via::Ways(ref via_ways) => {
+                let via: Vec<GraphEdgeId> = via_ways
+                    .iter()
+                    .filter_map(|w| way_edges.get(w))
+                    .flatten()
+                    .cloned()
+                    .collect();
+                if let Some(edges) = find_via_way_path(g, from, &via, to) {
+                    result.push(TurnRestriction {
+                        kind: restriction.kind,
+                        edges,
+                    });
+                }
+            }
+        }
+    }
+
+    info!(
+        "mapped {}/{} turn restrictions: {}s",
+        result.len(),
+        restrictions.len(),
+        now.elapsed().as_secs()
+    );
+
+    result
+}
+
+fn can_enter(g: &Graph<NodeData, EdgeData>, e: GraphEdgeId, node: GraphNodeId) -> bool {
+    let edge = g.edge(e);
+    edge.t == node || (edge.data.bidirectional && edge.s == node)
+}
+
+fn can_leave(g: &Graph<NodeData, EdgeData>, e: GraphEdgeId, node: GraphNodeId) -> bool {
+    let edge = g.edge(e);
+    edge.s == node || (edge.data.bidirectional && edge.t == node)
+}
+
+// the edge of a from (`enter`) or to way at the via node; if the way passes
+// the via node, the part ending there leads in and the part starting there out
+fn via_node_edge(
+    g: &Graph<NodeData, EdgeData>,
+    edges: &[GraphEdgeId],
+    via: GraphNodeId,
+    enter: bool,
+) -> Option<GraphEdgeId> {
+    let incident: Vec<GraphEdgeId> = edges
+        .iter()
+        .filter(|e| g.edge(**e).s == via || g.edge(**e).t == via)
+        .cloned()
+        .collect();
+    let e = match incident.len() {
+        1 => incident[0],
+        _ => *incident.iter().find(|e| {
+            let edge = g.edge(**e);
+            if enter {
+                edge.t == via
+            } else {
+                edge.s == via
+            }
+        })?,
+    };
+
+    let usable = if enter {
+        can_enter(g, e, via)
+    } else {
+        can_leave(g, e, via)
+    };
+    if usable {
+        Some(e)
+    } else {
+        None
+    }
+}
+
+// shortest sequence from-edge, via-edges, to-edge using at least one via edge
+fn find_via_way_path(
+    g: &Graph<NodeData, EdgeData>,
+    from: &[GraphEdgeId],
+    via: &[GraphEdgeId],
+    to: &[GraphEdgeId],
+) -> Option<Vec<GraphEdgeId>> {
+    let mut via_edges_at: HashMap<GraphNodeId, Vec<GraphEdgeId>> = HashMap::new();
+    for e in via {
+        let edge = g.edge(*e);
+        via_edges_at.entry(edge.s).or_default().push(*e);
+        via_edges_at.entry(edge.t).or_default().push(*e);
+    }
+
+    let mut best: Option<Vec<GraphEdgeId>> = None;
+    for f in from {
+        let edge = g.edge(*f);
+        for start in &[edge.s, edge.t] {
+            if !can_enter(g, *f, *start) {
+                continue;
+            }
+
+            // breadth first search over the via edges, remembering the edge used to reach a node
+            let mut parent: HashMap<GraphNodeId, GraphEdgeId> = HashMap::new();
+            let mut queue = VecDeque::new();
+            queue.push_back(*start);
+
+            while let Some(u) = queue.pop_front() {
+                if u != *start {
+                    if let Some(t) = to.iter().find(|t| can_leave(g, **t, u)) {
+                        let mut path = vec![*t];
+                        let mut current = u;
+                        while current != *start {
+                            let e = parent[&current];
+                            path.push(e);
+                            current = g.edge(e).other(current);
+                        }
+                        path.push(*f);
+                        path.reverse();
+
+                        if best.as_ref().is_none_or(|b| path.len() < b.len()) {
+                            best = Some(path);
+                        }
+                        break;
+                    }
+                }
+
+                for e in via_edges_at.get(&u).map_or(&[][..], |x| &x[..]) {
+                    let v = g.edge(*e).other(u);
+                    if can_leave(g, *e, u) && v != *start && !parent.contains_key(&v) {
+                        parent.insert(v, *e);
+                        queue.push_back(v);
+                    }
+                }
+            }
+        }
+    }
+
+    best
+}
+
+// TESTS
+#[cfg(test)]
+use osmpbfreader::Ref;
+
+#[cfg(test)]
+fn create_relation(tags: &[(&str, &str)], refs: Vec<(OsmId, &str)>) -> Relation {
+    Relation {
+        id: RelationId(1),
+        tags: tags
+            .iter()
+            .map(|(k, v)| ((*k).into(), (*v).into()))
+            .collect(),
+        refs: refs
+            .into_iter()
+            .map(|(member, role)| Ref {
+                member,
+                role: role.into(),
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+fn create_graph(edges: &[(u32, u32, i64, bool)], num_nodes: u32) -> Graph<NodeData, EdgeData> {
+    let mut g = Graph {
+        nodes: Vec::new(),
+        edges: Vec::new(),
+    };
+    for i in 0..num_nodes {
+        g.add_node(NodeData {
+            osm_id: NodeId(100 + i as i64),
+            lat: 0.0,
+            lon: 0.0,
+        });
+    }
+    for (s, t, way_id, bidirectional) in edges {
+        let data = EdgeData {
+            osm_way_id: WayId(*way_id),
+            osm_way_piece: 0,
+            name: "".to_string(),
+            length: 1.0,
+            street_type: "residential".to_string(),
+            max_speed: 30,
+            bidirectional: *bidirectional,
+            geometry: Vec::new(),
+        };
+        g.add_edge(GraphNodeId(*s), GraphNodeId(*t), data, *bidirectional);
+    }
+    g
+}
+
+#[cfg(test)]
+fn edge_ids(restriction: &TurnRestriction) -> Vec<u32> {
+    restriction.edges.iter().map(|e| e.0).collect()
+}
+
+#[test]
+fn should_parse_via_node_restriction() {
+    let relation = create_relation(
+        &[("type", "restriction"), ("restriction", "no_left_turn")],
+        vec![
+            (OsmId::Way(WayId(1)), "from"),
+            (OsmId::Node(NodeId(5)), "via"),
+            (OsmId::Way(WayId(2)), "to"),
+        ],
+    );
+
+    let result = parse_restrictions(&[relation], &NetworkType::Car);
+
+    assert!(result.len() == 1);
+    assert!(result[0].kind == RestrictionKind::No);
+    assert!(result[0].from == WayId(1));
+    assert!(result[0].via == Via::Node(NodeId(5)));
+    assert!(result[0].to == WayId(2));
+}
+
+#[test]
+fn should_respect_mode_specific_restrictions_and_exceptions() {
+    let refs = || {
+        vec![
+            (OsmId::Way(WayId(1)), "from"),
+            (OsmId::Way(WayId(3)), "via"),
+            (OsmId::Way(WayId(2)), "to"),
+        ]
+    };
+    let only_bicycles = create_relation(
+        &[
+            ("type", "restriction"),
+            ("restriction:bicycle", "only_straight_on"),
+        ],
+        refs(),
+    );
+    let except_bicycles = create_relation(
+        &[
+            ("type", "restriction"),
+            ("restriction", "no_right_turn"),
+            ("except", "bicycle"),
+        ],
+        refs(),
+    );
+    let relations = [only_bicycles, except_bicycles];
+
+    let car = parse_restrictions(&relations, &NetworkType::Car);
+    let bicycle = parse_restrictions(&relations, &NetworkType::Bicycle);
+    let pedestrian = parse_restrictions(&relations, &NetworkType::Pedestrian);
+
+    assert!(car.len() == 1 && car[0].kind == RestrictionKind::No);
+    assert!(bicycle.len() == 1 && bicycle[0].kind == RestrictionKind::Only);
+    assert!(bicycle[0].via == Via::Ways(vec![WayId(3)]));
+    assert!(pedestrian.is_empty());
+}
+
+#[test]
+fn should_map_via_node_restriction_to_edges() {
+    // way 1: 0 -> 1 (oneway), way 2: 1 - 2, way 3: 1 - 3
+    let g = create_graph(&[(0, 1, 1, false), (1, 2, 2, true), (1, 3, 3, true)], 4);
+    let restriction = OsmRestriction {
+        id: RelationId(1),
+        kind: RestrictionKind::No,
+        from: WayId(1),
+        via: Via::Node(NodeId(101)),
+        to: WayId(3),
+    };
+
+    let result = map_restrictions(&[restriction], &g);
+
+    assert!(result.len() == 1);
+    assert!(edge_ids(&result[0]) == vec![0, 2]);
+    assert!(result[0].description() == "no 0 2");
+}
+
+#[test]
+fn should_map_via_node_restriction_onto_ways_split_at_the_via_node() {
+    // way 1: 0 - 1 - 2 (split at the via node 1), way 2: 1 - 3
+    let g = create_graph(&[(0, 1, 1, true), (1, 2, 1, true), (1, 3, 2, true)], 4);
+    let restriction = |from: i64, to: i64| OsmRestriction {
+        id: RelationId(1),
+        kind: RestrictionKind::No,
+        from: WayId(from),
+        via: Via::Node(NodeId(101)),
+        to: WayId(to),
+    };
+
+    let result = map_restrictions(&[restriction(2, 1), restriction(1, 2)], &g);
+
+    assert!(result.len() == 2);
+    assert!(edge_ids(&result[0]) == vec![2, 1]);
+    assert!(edge_ids(&result[1]) == vec![0, 2]);
+}
+
+#[test]
+fn should_map_u_turn_restriction_to_same_edge() {
+    // way 1: 0 - 1 - 2 (split at the via node 1), way 2: 1 - 3, way 3: 1 -> 4 (oneway)
+    let g = create_graph(
+        &[
+            (0, 1, 1, true),
+            (1, 2, 1, true),
+            (1, 3, 2, true),
+            (1, 4, 3, false),
+        ],
+        5,
+    );
+    let no_u_turn = |way: i64| OsmRestriction {
+        id: RelationId(1),
+        kind: RestrictionKind::No,
+        from: WayId(way),
+        via: Via::Node(NodeId(101)),
+        to: WayId(way),
+    };
+
+    let result = map_restrictions(&[no_u_turn(1), no_u_turn(2), no_u_turn(3)], &g);
+
+    assert!(result.len() == 2);
+    assert!(edge_ids(&result[0]) == vec![0, 0]);
+    assert!(edge_ids(&result[1]) == vec![2, 2]);
+}
+
+#[test]
+fn should_map_via_way_restriction_to_edges() {
+    // way 1: 0 - 1, way 3: 1 - 2 - 3 (split at 2), way 2: 3 - 4, plus a side street 2 - 5
+    let g = create_graph(
+        &[
+            (0, 1, 1, true),
+            (1, 2, 3, true),
+            (2, 3, 3, true),
+            (3, 4, 2, true),
+            (2, 5, 4, true),
+        ],
+        6,
+    );
+    let restriction = OsmRestriction {
+        id: RelationId(1),
+        kind: RestrictionKind::Only,
+        from: WayId(1),
+        via: Via::Ways(vec![WayId(3)]),
+        to: WayId(2),
+    };
+
+    let result = map_restrictions(&[restriction], &g);
+
+    assert!(result.len() == 1);
+    assert!(edge_ids(&result[0]) == vec![0, 1, 2, 3]);
+    assert!(result[0].description() == "only 0 1 2 3");
+}
+
+#[test]
+fn should_drop_restrictions_outside_of_graph() {
+    let g = create_graph(&[(0, 1, 1, true)], 2);
+    let restriction = OsmRestriction {
+        id: RelationId(1),
+        kind: RestrictionKind::No,
+        from: WayId(1),
+        via: Via::Node(NodeId(101)),
+        to: WayId(7),
+    };
+
+    let result = map_restrictions(&[restriction], &g);
+
+    assert!(result.is_empty());
+}
@@ -0,0 +1,431 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use error::Error;
+use graph::{Graph, GraphEdgeId, GraphNodeId};
+use graph_data::{EdgeData, NodeData};
+use util;
+
+// what a route minimizes
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Cost {
+    // meters
+    Distance,
+    // seconds when driving at max_speed
+    TravelTime,
+}
+
+pub fn get_cost(cost: &str) -> Result<Cost, Error> {
+    match cost {
+        "distance" => Ok(Cost::Distance),
+        "time" => Ok(Cost::TravelTime),
+        _ => Err(Error::Argument(format!("did not recognize cost: {}", cost))),
+    }
+}
+
+// parses "lat,lon"
+pub fn parse_coordinate(coordinate: &str) -> Result<(f64, f64), Error> {
+    let values = coordinate
+        .split(',')
+        .map(|x| {
+            x.trim()
+                .parse::<f64>()
+                .map_err(|_| Error::Argument(format!("invalid coordinate value: {}", x)))
+        })
+        .collect::<Result<Vec<f64>, Error>>()?;
+
+    if values.len() != 2 {
+        return Err(Error::Argument(format!(
+            "coordinate has to be given as lat,lon: {}",
+            coordinate
+        )));
+    }
+
+    Ok((values[0], values[1]))
+}
+
+pub struct Path {
+    pub nodes: Vec<GraphNodeId>,
+    pub edges: Vec<GraphEdgeId>,
+    // meters
+    pub distance: f64,
+    // seconds
+    pub duration: f64,
+}
+
+pub fn edge_cost(data: &EdgeData, cost: Cost) -> f64 {
+    match cost {
+        Cost::Distance => data.length,
+        Cost::TravelTime => data.travel_time(),
+    }
+}
+
+pub fn dijkstra<E: AsRef<EdgeData>>(
+    g: &Graph<NodeData, E>,
+    s: GraphNodeId,
+    t: GraphNodeId,
+    cost: Cost,
+) -> Option<Path> {
+    search(g, s, t, cost, |_| 0.0)
+}
+
+// A* with the great-circle distance to the target as lower bound; for travel
+// time it is divided by the highest max_speed in the graph
+pub fn astar<E: AsRef<EdgeData>>(
+    g: &Graph<NodeData, E>,
+    s: GraphNodeId,
+    t: GraphNodeId,
+    cost: Cost,
+) -> Option<Path> {
+    let target = &g.nodes[t.0 as usize].data;
+    let target = (target.lat, target.lon);
+    let factor = match cost {
+        Cost::Distance => 1.0,
+        Cost::TravelTime => {
+            let max_speed = g
+                .edges
+                .iter()
+                .map(|e| e.data.as_ref().max_speed)
+                .max()
+                .unwrap_or(1)
+                .max(1);
+            3.6 / max_speed as f64
+        }
+    };
+
+    search(g, s, t, cost, |v| {
+        let node = &g.nodes[v.0 as usize].data;
+        util::haversine_distance((node.lat, node.lon), target) * factor
+    })
+}
+
+// costs from s to every target, None for unreachable targets; the search
+// stops as soon as all targets are settled
+pub fn one_to_many<E: AsRef<EdgeData>>(
+    g: &Graph<NodeData, E>,
+    s: GraphNodeId,
+    targets: &[GraphNodeId],
+    cost: Cost,
+) -> Vec<Option<f64>> {
+    let mut is_target = vec![false; g.nodes.len()];
+    for t in targets {
+        is_target[t.0 as usize] = true;
+    }
+    let mut remaining = is_target.iter().filter(|x| **x).count();
+
+    let mut distances = vec![f64::INFINITY; g.nodes.len()];
+    let mut settled = vec![false; g.nodes.len()];
+    let mut heap = BinaryHeap::new();
+    distances[s.0 as usize] = 0.0;
+    heap.push(State { key: 0.0, node: s });
+
+    while let Some(State { node, .. }) = heap.pop() {
+        let u = node.0 as usize;
+        if settled[u] {
+            continue;
+        }
+        settled[u] = true;
+        if is_target[u] {
+            remaining -= 1;
+            if remaining == 0 {
+                break;
+            }
+        }
+
+        for e in g.nodes[u].get_edges() {
+            let edge = g.edge(*e);
+            let v = edge.other(node);
+            let distance = distances[u] + edge_cost(edge.data.as_ref(), cost);
+            if distance < distances[v.0 as usize] {
+                distances[v.0 as usize] = distance;
+                heap.push(State {
+                    key: distance,
+                    node: v,
+                });
+            }
+        }
+    }
+
+    targets
+        .iter()
+        .map(|t| Some(distances[t.0 as usize]).filter(|d| d.is_finite()))
+        .collect()
+}
+
+// costs from s to all nodes reachable within `budget`, infinity for all other nodes
+pub fn costs_within<E: AsRef<EdgeData>>(
+    g: &Graph<NodeData, E>,
+    s: GraphNodeId,
+    cost: Cost,
+    budget: f64,
+) -> Vec<f64> {
+    let mut distances = vec![f64::INFINITY; g.nodes.len()];
+    let mut heap = BinaryHeap::new();
+    distances[s.0 as usize] = 0.0;
+    heap.push(State { key: 0.0, node: s });
+
+    while let Some(State { key, node }) = heap.pop() {
+        let u = node.0 as usize;
+        if key > distances[u] {
+            continue;
+        }
+
+        for e in g.nodes[u].get_edges() {
+            let edge = g.edge(*e);
+            let v = edge.other(node);
+            let distance = key + edge_cost(edge.data.as_ref(), cost);
+            if distance <= budget && distance < distances[v.0 as usize] {
+                distances[v.0 as usize] = distance;
+                heap.push(State {
+                    key: distance,
+                    node: v,
+                });
+            }
+        }
+    }
+
+    distances
+}
+
+#[derive(PartialEq)]
+struct State {
+    // cost from the source plus the heuristic
+    key: f64,
+    node: GraphNodeId,
+}
+
+impl Eq for State {}
+
+// reversed, so that BinaryHeap pops the smallest key first
+impl Ord for State {
+    fn cmp(&self, other: &State) -> Ordering {
+        other
+            .key
+            .partial_cmp(&self.key)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| other.node.0.cmp(&self.node.0))
+    }
+}
+
+impl PartialOrd for State {
+    fn partial_cmp(&self, other: &State) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// edges may be traversed from their source, and from their target if they are bidirectional
+fn search<E: AsRef<EdgeData>, H: Fn(GraphNodeId) -> f64>(
+    g: &Graph<NodeData, E>,
+    s: GraphNodeId,
+    t: GraphNodeId,
+    cost: Cost,
+    heuristic: H,
+) -> Option<Path> {
+    let mut distances = vec![f64::INFINITY; g.nodes.len()];
+    let mut predecessors: Vec<Option<GraphEdgeId>> = vec![None; g.nodes.len()];
+    let mut settled = vec![false; g.nodes.len()];
+    let mut heap = BinaryHeap::new();
+
+    distances[s.0 as usize] = 0.0;
+    heap.push(State {
+        key: heuristic(s),
+        node: s,
+    });
+
+    while let Some(State { node, .. }) = heap.pop() {
+        let u = node.0 as usize;
+        if settled[u] {
+            continue;
+        }
+        settled[u] = true;
+        if node == t {
+            break;
+        }
+
+        for e in g.nodes[u].get_edges() {
+            let edge = g.edge(*e);
+            let v = edge.other(node);
+            let distance = distances[u] + edge_cost(edge.data.as_ref(), cost);
+            if distance < distances[v.0 as usize] {
+                distances[v.0 as usize] = distance;
+                predecessors[v.0 as usize] = Some(*e);
+                heap.push(State {
+                    key: distance + heuristic(v),
+                    node: v,
+                });
+            }
+        }
+    }
+
+    if !settled[t.0 as usize] {
+        return None;
+    }
+
+    let mut path = Path {
+        nodes: vec![t],
+        edges: Vec::new(),
+        distance: 0.0,
+        duration: 0.0,
+    };
+    let mut current = t;
+    while let Some(e) = predecessors[current.0 as usize] {
+        let edge = g.edge(e);
+        path.edges.push(e);
+        path.distance += edge.data.as_ref().length;
+        path.duration += edge.data.as_ref().travel_time();
+        current = edge.other(current);
+        path.nodes.push(current);
+    }
+    path.nodes.reverse();
+    path.edges.reverse();
+
+    Some(path)
+}
+
+// TESTS
+#[cfg(test)]
+use osmpbfreader::{NodeId, WayId};
+
+// a 3x3 grid with 0.001 degree spacing; the direct way along the bottom row
+// is slow, the detour over the middle row is fast. The edge 4 -> 5 is oneway.
+#[cfg(test)]
+fn create_graph() -> Graph<NodeData, EdgeData> {
+    let mut g = Graph {
+        nodes: Vec::new(),
+        edges: Vec::new(),
+    };
+    for i in 0..9 {
+        g.add_node(NodeData {
+            osm_id: NodeId(i),
+            lat: 52.0 + 0.001 * (i / 3) as f64,
+            lon: 13.0 + 0.001 * (i % 3) as f64,
+        });
+    }
+    let edges = [
+        (0, 1, 10, true),
+        (1, 2, 10, true),
+        (0, 3, 100, true),
+        (3, 4, 100, true),
+        (4, 5, 100, false),
+        (5, 2, 100, true),
+        (3, 6, 30, true),
+        (6, 7, 30, true),
+        (7, 8, 30, true),
+    ];
+    for (s, t, max_speed, bidirectional) in edges.iter() {
+        let a = (g.nodes[*s].data.lat, g.nodes[*s].data.lon);
+        let b = (g.nodes[*t].data.lat, g.nodes[*t].data.lon);
+        let data = EdgeData {
+            osm_way_id: WayId(0),
+            osm_way_piece: 0,
+            name: String::new(),
+            length: util::haversine_distance(a, b),
+            street_type: "residential".to_string(),
+            max_speed: *max_speed,
+            bidirectional: *bidirectional,
+            geometry: vec![a, b],
+        };
+        g.add_edge(
+            GraphNodeId(*s as u32),
+            GraphNodeId(*t as u32),
+            data,
+            *bidirectional,
+        );
+    }
+    g
+}
+
+#[cfg(test)]
+fn node_ids(path: &Path) -> Vec<u32> {
+    path.nodes.iter().map(|n| n.0).collect()
+}
+
+#[test]
+fn should_find_shortest_and_fastest_path() {
+    let g = create_graph();
+    let (s, t) = (GraphNodeId(0), GraphNodeId(2));
+
+    for search in [dijkstra, astar].iter() {
+        let shortest = search(&g, s, t, Cost::Distance).unwrap();
+        assert!(node_ids(&shortest) == vec![0, 1, 2]);
+        assert!(shortest.edges == vec![GraphEdgeId(0), GraphEdgeId(1)]);
+        assert!((shortest.distance - 136.9).abs() < 1.0);
+        assert!((shortest.duration - shortest.distance / (10.0 / 3.6)).abs() < 1e-6);
+
+        let fastest = search(&g, s, t, Cost::TravelTime).unwrap();
+        assert!(node_ids(&fastest) == vec![0, 3, 4, 5, 2]);
+        assert!(fastest.duration < shortest.duration);
+    }
+}
+
+#[test]
+fn should_respect_oneway_edges() {
+    let g = create_graph();
+
+    for search in [dijkstra, astar].iter() {
+        let path = search(&g, GraphNodeId(5), GraphNodeId(4), Cost::TravelTime).unwrap();
+        assert!(node_ids(&path) == vec![5, 2, 1, 0, 3, 4]);
+
+        let path = search(&g, GraphNodeId(8), GraphNodeId(8), Cost::Distance).unwrap();
+        assert!(node_ids(&path) == vec![8] && path.edges.is_empty());
+    }
+}
+
+#[test]
+fn should_compute_one_to_many_costs() {
+    let mut g = create_graph();
+    g.add_node(NodeData {
+        osm_id: NodeId(9),
+        lat: 53.0,
+        lon: 14.0,
+    });
+    let targets = [
+        GraphNodeId(2),
+        GraphNodeId(9),
+        GraphNodeId(0),
+        GraphNodeId(4),
+    ];
+
+    let costs = one_to_many(&g, GraphNodeId(0), &targets, Cost::TravelTime);
+
+    assert!(costs.len() == 4);
+    assert!(costs[1].is_none());
+    assert!(costs[2] == Some(0.0));
+    for i in [0, 3].iter() {
+        let path = dijkstra(&g, GraphNodeId(0), targets[*i], Cost::TravelTime).unwrap();
+        assert!((costs[*i].unwrap() - path.duration).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn should_compute_costs_within_budget() {
+    let g = create_graph();
+
+    let costs = costs_within(&g, GraphNodeId(0), Cost::Distance, 150.0);
+
+    let reachable: Vec<usize> = (0..9).filter(|i| costs[*i].is_finite()).collect();
+    assert!(reachable == vec![0, 1, 2, 3]);
+    assert!(costs[0] == 0.0);
+    assert!((costs[3] - 111.2).abs() < 0.1);
+}
+
+#[test]
+fn should_return_none_for_unreachable_target() {
+    let mut g = create_graph();
+    g.add_node(NodeData {
+        osm_id: NodeId(9),
+        lat: 53.0,
+        lon: 14.0,
+    });
+
+    assert!(dijkstra(&g, GraphNodeId(0), GraphNodeId(9), Cost::Distance).is_none());
+    assert!(astar(&g, GraphNodeId(0), GraphNodeId(9), Cost::Distance).is_none());
+}
+
+#[test]
+fn should_parse_coordinate() {
+    assert!(parse_coordinate("52.5,-13.25").unwrap() == (52.5, -13.25));
+    assert!(parse_coordinate(" 1 , 2 ").unwrap() == (1.0, 2.0));
+    assert!(parse_coordinate("52.5").is_err());
+    assert!(parse_coordinate("52.5,x").is_err());
+}
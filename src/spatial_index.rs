@@ -0,0 +1,501 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use graph::{Graph, GraphEdgeId, GraphNodeId};
+use graph_data::{EdgeData, NodeData};
+use util;
+
+// side length of a grid cell in degrees, roughly 1km in latitude
+pub const DEFAULT_CELL_SIZE: f64 = 0.01;
+
+pub struct NodeMatch {
+    pub node: GraphNodeId,
+    // meters
+    pub distance: f64,
+}
+
+pub struct EdgeMatch {
+    pub edge: GraphEdgeId,
+    // meters between the query point and its projection onto the edge
+    pub distance: f64,
+    // projection of the query point onto the edge geometry
+    pub lat: f64,
+    pub lon: f64,
+    // position of the projection along the edge from s (0.0) to t (1.0), by length
+    pub fraction: f64,
+}
+
+// uniform grid over the nodes and the edge segments of a graph; an edge
+// segment is registered in every cell it crosses
+pub struct SpatialIndex<'a, E: 'a> {
+    g: &'a Graph<NodeData, E>,
+    cell_size: f64,
+    nodes: HashMap<(i32, i32), Vec<GraphNodeId>>,
+    // edge and index of the first point of the segment in the edge geometry
+    segments: HashMap<(i32, i32), Vec<(GraphEdgeId, u32)>>,
+    // meters along the edge geometry to each of its points, by edge id
+    distances: Vec<Vec<f64>>,
+    // (min_lat, min_lon, max_lat, max_lon) of all occupied cells
+    cell_bounds: Option<(i32, i32, i32, i32)>,
+    // cosine of the largest absolute latitude, to bound longitudinal distances
+    min_cos_lat: f64,
+}
+
+impl<'a, E: AsRef<EdgeData>> SpatialIndex<'a, E> {
+    pub fn new(g: &'a Graph<NodeData, E>) -> SpatialIndex<'a, E> {
+        SpatialIndex::with_cell_size(g, DEFAULT_CELL_SIZE)
+    }
+
+    pub fn with_cell_size(g: &'a Graph<NodeData, E>, cell_size: f64) -> SpatialIndex<'a, E> {
+        let mut index = SpatialIndex {
+            g,
+            cell_size,
+            nodes: HashMap::new(),
+            segments: HashMap::new(),
+            distances: Vec::with_capacity(g.edges.len()),
+            cell_bounds: None,
+            min_cos_lat: 1.0,
+        };
+
+        for node in &g.nodes {
+            let cell = index.cell(node.data.lat, node.data.lon);
+            index.nodes.entry(cell).or_default().push(node.id);
+            index.extend_bounds(cell, node.data.lat);
+        }
+
+        for edge in &g.edges {
+            let geometry = edge_geometry(g, edge.id);
+            let mut distances = vec![0.0];
+            for (i, w) in geometry.windows(2).enumerate() {
+                for cell in segment_cells(w[0], w[1], cell_size) {
+                    index
+                        .segments
+                        .entry(cell)
+                        .or_default()
+                        .push((edge.id, i as u32));
+                }
+                index.extend_bounds(index.cell(w[0].0, w[0].1), w[0].0);
+                index.extend_bounds(index.cell(w[1].0, w[1].1), w[1].0);
+                distances.push(distances[i] + util::haversine_distance(w[0], w[1]));
+            }
+            index.distances.push(distances);
+        }
+
+        index
+    }
+
+    // the k nodes closest to (lat, lon), closest first
+    pub fn nearest_nodes(&self, lat: f64, lon: f64, k: usize) -> Vec<NodeMatch> {
+        let mut result = Vec::new();
+        if k == 0 {
+            return result;
+        }
+
+        for r in 0..=self.max_ring(lat, lon) {
+            if result.len() >= k && result[k - 1].distance <= self.ring_distance(r, lat) {
+                break;
+            }
+            for cell in ring_cells(self.cell(lat, lon), r) {
+                if let Some(nodes) = self.nodes.get(&cell) {
+                    result.extend(nodes.iter().map(|n| self.node_match(*n, lat, lon)));
+                }
+            }
+            result.sort_by(|a, b| by_distance(a.distance, b.distance));
+        }
+
+        result.truncate(k);
+        result
+    }
+
+    pub fn nearest_node(&self, lat: f64, lon: f64) -> Option<NodeMatch> {
+        self.nearest_nodes(lat, lon, 1).pop()
+    }
+
+    // all nodes at most `radius` meters away from (lat, lon), closest first
+    pub fn nodes_within(&self, lat: f64, lon: f64, radius: f64) -> Vec<NodeMatch> {
+        let mut result = Vec::new();
+
+        for r in 0..=self.max_ring(lat, lon) {
+            if self.ring_distance(r, lat) > radius {
+                break;
+            }
+            for cell in ring_cells(self.cell(lat, lon), r) {
+                if let Some(nodes) = self.nodes.get(&cell) {
+                    result.extend(
+                        nodes
+                            .iter()
+                            .map(|n| self.node_match(*n, lat, lon))
+                            .filter(|m| m.distance <= radius),
+                    );
+                }
+            }
+        }
+
+        result.sort_by(|a, b| by_distance(a.distance, b.distance));
+        result
+    }
+
+    // the edge closest to (lat, lon) with the projection onto its geometry
+    pub fn nearest_edge(&self, lat: f64, lon: f64) -> Option<EdgeMatch> {
+        let mut best: Option<EdgeMatch> = None;
+
+        for r in 0..=self.max_ring(lat, lon) {
+            if let Some(ref m) = best {
+                if m.distance <= self.ring_distance(r, lat) {
+                    break;
+                }
+            }
+            for cell in ring_cells(self.cell(lat, lon), r) {
+                for (edge, i) in self.segments.get(&cell).into_iter().flatten() {
+                    let m = self.edge_match(*edge, *i as usize, lat, lon);
+                    if best.as_ref().is_none_or(|b| m.distance < b.distance) {
+                        best = Some(m);
+                    }
+                }
+            }
+        }
+
+        best
+    }
+
+    // all edges passing at most `radius` meters from (lat, lon), each with
+    // its closest point, closest first
+    pub fn edges_within(&self, lat: f64, lon: f64, radius: f64) -> Vec<EdgeMatch> {
+        let mut matches: HashMap<GraphEdgeId, EdgeMatch> = HashMap::new();
+
+        for r in 0..=self.max_ring(lat, lon) {
+            if self.ring_distance(r, lat) > radius {
+                break;
+            }
+            for cell in ring_cells(self.cell(lat, lon), r) {
+                for (edge, i) in self.segments.get(&cell).into_iter().flatten() {
+                    let m = self.edge_match(*edge, *i as usize, lat, lon);
+                    if m.distance > radius {
+                        continue;
+                    }
+                    let better = matches
+                        .get(edge)
+                        .is_none_or(|other| m.distance < other.distance);
+                    if better {
+                        matches.insert(*edge, m);
+                    }
+                }
+            }
+        }
+
+        let mut result: Vec<EdgeMatch> = matches.into_values().collect();
+        result.sort_by(|a, b| by_distance(a.distance, b.distance).then(a.edge.0.cmp(&b.edge.0)));
+        result
+    }
+
+    fn cell(&self, lat: f64, lon: f64) -> (i32, i32) {
+        (
+            (lat / self.cell_size).floor() as i32,
+            (lon / self.cell_size).floor() as i32,
+        )
+    }
+
+    fn extend_bounds(&mut self, cell: (i32, i32), lat: f64) {
+        self.cell_bounds = Some(match self.cell_bounds {
+            None => (cell.0, cell.1, cell.0, cell.1),
+            Some((a, b, c, d)) => (a.min(cell.0), b.min(cell.1), c.max(cell.0), d.max(cell.1)),
+        });
+        self.min_cos_lat = self.min_cos_lat.min(lat.to_radians().cos());
+    }
+
+    // the last ring around the cell of (lat, lon) that contains occupied cells
+    fn max_ring(&self, lat: f64, lon: f64) -> i32 {
+        let (lat, lon) = self.cell(lat, lon);
+        match self.cell_bounds {
+            None => -1,
+            Some((min_lat, min_lon, max_lat, max_lon)) => (lat - min_lat)
+                .abs()
+                .max((lat - max_lat).abs())
+                .max((lon - min_lon).abs())
+                .max((lon - max_lon).abs()),
+        }
+    }
+
+    // lower bound in meters for the distance between (lat, _) and any point
+    // in a cell of ring r around its own cell
+    fn ring_distance(&self, r: i32, lat: f64) -> f64 {
+        let cos_lat = self.min_cos_lat.min(lat.to_radians().cos()).max(0.0);
+        (r - 1).max(0) as f64 * self.cell_size * util::METERS_PER_DEGREE * cos_lat
+    }
+
+    fn node_match(&self, node: GraphNodeId, lat: f64, lon: f64) -> NodeMatch {
+        let data = &self.g.nodes[node.0 as usize].data;
+        NodeMatch {
+            node,
+            distance: util::haversine_distance((lat, lon), (data.lat, data.lon)),
+        }
+    }
+
+    // projects (lat, lon) onto the i-th segment of the edge geometry
+    fn edge_match(&self, edge: GraphEdgeId, i: usize, lat: f64, lon: f64) -> EdgeMatch {
+        let (a, b) = self.segment(edge, i);
+
+        // equirectangular projection around the query point
+        let cos_lat = lat.to_radians().cos();
+        let (ax, ay) = ((a.1 - lon) * cos_lat, a.0 - lat);
+        let (bx, by) = ((b.1 - lon) * cos_lat, b.0 - lat);
+        let (dx, dy) = (bx - ax, by - ay);
+        let length_squared = dx * dx + dy * dy;
+        let t = if length_squared > 0.0 {
+            (-(ax * dx + ay * dy) / length_squared).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let point = (a.0 + t * (b.0 - a.0), a.1 + t * (b.1 - a.1));
+
+        let distances = &self.distances[edge.0 as usize];
+        let total = distances[distances.len() - 1];
+        let fraction = if total > 0.0 {
+            (distances[i] + t * (distances[i + 1] - distances[i])) / total
+        } else {
+            0.0
+        };
+
+        EdgeMatch {
+            edge,
+            distance: util::haversine_distance((lat, lon), point),
+            lat: point.0,
+            lon: point.1,
+            fraction,
+        }
+    }
+
+    // end points of the i-th segment of the edge geometry, see edge_geometry
+    fn segment(&self, edge: GraphEdgeId, i: usize) -> ((f64, f64), (f64, f64)) {
+        let edge = self.g.edge(edge);
+        let geometry = &edge.data.as_ref().geometry;
+        if geometry.len() >= 2 {
+            (geometry[i], geometry[i + 1])
+        } else {
+            let s = &self.g.nodes[edge.s.0 as usize].data;
+            let t = &self.g.nodes[edge.t.0 as usize].data;
+            ((s.lat, s.lon), (t.lat, t.lon))
+        }
+    }
+}
+
+// the edge geometry, or the straight line between its end points if it has none
+pub fn edge_geometry<E: AsRef<EdgeData>>(
+    g: &Graph<NodeData, E>,
+    edge: GraphEdgeId,
+) -> Vec<(f64, f64)> {
+    let edge = g.edge(edge);
+    let geometry = &edge.data.as_ref().geometry;
+    if geometry.len() >= 2 {
+        geometry.clone()
+    } else {
+        let s = &g.nodes[edge.s.0 as usize].data;
+        let t = &g.nodes[edge.t.0 as usize].data;
+        vec![(s.lat, s.lon), (t.lat, t.lon)]
+    }
+}
+
+// cells crossed by the straight segment from a to b, row by row: each row
+// of cells is crossed between the longitudes at which the segment enters and
+// leaves the latitudes of the row
+fn segment_cells(a: (f64, f64), b: (f64, f64), cell_size: f64) -> Vec<(i32, i32)> {
+    let (a, b) = if a.0 <= b.0 { (a, b) } else { (b, a) };
+    let row = |lat: f64| (lat / cell_size).floor() as i32;
+    let column = |lon: f64| (lon / cell_size).floor() as i32;
+    let lon_at = |lat: f64| {
+        if lat <= a.0 {
+            a.1
+        } else if lat >= b.0 {
+            b.1
+        } else {
+            a.1 + (lat - a.0) / (b.0 - a.0) * (b.1 - a.1)
+        }
+    };
+
+    let mut cells = Vec::new();
+    for r in row(a.0)..=row(b.0) {
+        let c = column(lon_at(r as f64 * cell_size));
+        let d = column(lon_at((r + 1) as f64 * cell_size));
+        for lon in c.min(d)..=c.max(d) {
+            cells.push((r, lon));
+        }
+    }
+    cells
+}
+
+// cells with a Chebyshev distance of exactly r to `center`
+fn ring_cells(center: (i32, i32), r: i32) -> Vec<(i32, i32)> {
+    if r == 0 {
+        return vec![center];
+    }
+
+    let mut cells = Vec::with_capacity(8 * r as usize);
+    for d in -r..=r {
+        cells.push((center.0 - r, center.1 + d));
+        cells.push((center.0 + r, center.1 + d));
+    }
+    for d in (-r + 1)..r {
+        cells.push((center.0 + d, center.1 - r));
+        cells.push((center.0 + d, center.1 + r));
+    }
+    cells
+}
+
+fn by_distance(a: f64, b: f64) -> Ordering {
+    a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+}
+
+// TESTS
+#[cfg(test)]
+use osmpbfreader::{NodeId, WayId};
+
+// nodes on a 0.01 degree grid of 5x5 nodes, edges along the rows; the edge
+// of row 4 bends through (52.045, 13.02)
+#[cfg(test)]
+fn create_graph() -> Graph<NodeData, EdgeData> {
+    let mut g = Graph {
+        nodes: Vec::new(),
+        edges: Vec::new(),
+    };
+    for i in 0..25 {
+        g.add_node(NodeData {
+            osm_id: NodeId(i),
+            lat: 52.0 + 0.01 * (i / 5) as f64,
+            lon: 13.0 + 0.01 * (i % 5) as f64,
+        });
+    }
+    for row in 0..5 {
+        let (s, t) = (5 * row, 5 * row + 4);
+        let (a, b) = (&g.nodes[s].data, &g.nodes[t].data);
+        let mut geometry = vec![(a.lat, a.lon), (b.lat, b.lon)];
+        if row == 4 {
+            geometry.insert(1, (52.045, 13.02));
+        }
+        let data = EdgeData {
+            osm_way_id: WayId(row as i64),
+            osm_way_piece: 0,
+            name: String::new(),
+            length: util::polyline_length(&geometry),
+            street_type: "residential".to_string(),
+            max_speed: 30,
+            bidirectional: true,
+            geometry,
+        };
+        g.add_edge(GraphNodeId(s as u32), GraphNodeId(t as u32), data, true);
+    }
+    g
+}
+
+#[cfg(test)]
+fn brute_force_nearest_nodes(g: &Graph<NodeData, EdgeData>, lat: f64, lon: f64) -> Vec<u32> {
+    let mut nodes: Vec<(f64, u32)> = g
+        .nodes
+        .iter()
+        .map(|n| {
+            let distance = util::haversine_distance((lat, lon), (n.data.lat, n.data.lon));
+            (distance, n.id.0)
+        })
+        .collect();
+    nodes.sort_by(|a, b| by_distance(a.0, b.0));
+    nodes.into_iter().map(|(_, id)| id).collect()
+}
+
+#[test]
+fn should_find_k_nearest_nodes() {
+    let g = create_graph();
+    // small cells, so that queries have to look at several rings
+    let index = SpatialIndex::with_cell_size(&g, 0.003);
+
+    let queries = [(52.021, 13.012), (51.9, 12.9), (52.3, 13.02), (52.0, 13.0)];
+    for (lat, lon) in queries.iter() {
+        let expected = brute_force_nearest_nodes(&g, *lat, *lon);
+        for k in [1, 3, 10, 30].iter() {
+            let result: Vec<u32> = index
+                .nearest_nodes(*lat, *lon, *k)
+                .iter()
+                .map(|m| m.node.0)
+                .collect();
+            assert!(result.len() == (*k).min(25));
+            assert!(result[..] == expected[..result.len()]);
+        }
+    }
+
+    let nearest = index.nearest_node(52.0, 13.0).unwrap();
+    assert!(nearest.node == GraphNodeId(0) && nearest.distance < 1e-6);
+}
+
+#[test]
+fn should_find_nodes_within_radius() {
+    let g = create_graph();
+    let index = SpatialIndex::with_cell_size(&g, 0.003);
+
+    // one degree of latitude is about 111km, one of longitude at 52° about 68km
+    let result: Vec<u32> = index
+        .nodes_within(52.02, 13.02, 1200.0)
+        .iter()
+        .map(|m| m.node.0)
+        .collect();
+    assert!(result[0] == 12);
+    let mut neighbors = result[1..].to_vec();
+    neighbors.sort();
+    assert!(neighbors == vec![7, 11, 13, 17]);
+
+    assert!(index.nodes_within(52.02, 13.025, 100.0).is_empty());
+}
+
+#[test]
+fn should_project_onto_nearest_edge() {
+    let g = create_graph();
+    let index = SpatialIndex::new(&g);
+
+    let m = index.nearest_edge(52.012, 13.01).unwrap();
+    assert!(m.edge == GraphEdgeId(1));
+    assert!((m.lat - 52.01).abs() < 1e-9 && (m.lon - 13.01).abs() < 1e-9);
+    assert!((m.fraction - 0.25).abs() < 1e-3);
+    assert!((m.distance - 222.4).abs() < 1.0);
+
+    // beyond the end of the edge the projection is its end point
+    let m = index.nearest_edge(52.0, 13.1).unwrap();
+    assert!(m.edge == GraphEdgeId(0));
+    assert!((m.lon - 13.04).abs() < 1e-9 && m.fraction == 1.0);
+
+    // on the bent edge the second segment is closest
+    let m = index.nearest_edge(52.046, 13.03).unwrap();
+    assert!(m.edge == GraphEdgeId(4));
+    assert!(m.fraction > 0.5 && m.lat > 52.04);
+}
+
+#[test]
+fn should_find_edges_within_radius() {
+    let g = create_graph();
+    let index = SpatialIndex::new(&g);
+
+    let mut result: Vec<u32> = index
+        .edges_within(52.015, 13.02, 600.0)
+        .iter()
+        .map(|m| m.edge.0)
+        .collect();
+    result.sort();
+    assert!(result == vec![1, 2]);
+    assert!(index.edges_within(52.015, 13.02, 500.0).is_empty());
+}
+
+#[test]
+fn should_register_segments_in_crossed_cells_only() {
+    let mut cells = segment_cells((0.5, 0.5), (2.5, 1.5), 1.0);
+    cells.sort();
+    assert!(cells == vec![(0, 0), (1, 0), (1, 1), (2, 1)]);
+
+    let mut cells = segment_cells((2.5, 0.2), (0.5, 0.8), 1.0);
+    cells.sort();
+    assert!(cells == vec![(0, 0), (1, 0), (2, 0)]);
+
+    let mut cells = segment_cells((0.5, -1.5), (0.5, 1.5), 1.0);
+    cells.sort();
+    assert!(cells == vec![(0, -2), (0, -1), (0, 0), (0, 1)]);
+
+    // a flat diagonal crosses several cells in each row
+    let mut cells = segment_cells((0.5, 0.5), (1.5, 4.5), 1.0);
+    cells.sort();
+    assert!(cells == vec![(0, 0), (0, 1), (0, 2), (1, 2), (1, 3), (1, 4)]);
+}
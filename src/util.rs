@@ -1,7 +1,104 @@
+use std::io;
+use std::io::prelude::*;
+
 pub fn keep_characters(original: &str, to_keep: &str) -> String {
     original.chars().filter(|&c| to_keep.contains(c)).collect()
 }
 
+const EARTH_RADIUS_M: f64 = 6_371_008.8;
+
+// length of one degree of latitude
+pub const METERS_PER_DEGREE: f64 = EARTH_RADIUS_M * std::f64::consts::PI / 180.0;
+
+// great-circle distance in meters between two (lat, lon) points given in degrees
+pub fn haversine_distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (lat1, lon1) = (a.0.to_radians(), a.1.to_radians());
+    let (lat2, lon2) = (b.0.to_radians(), b.1.to_radians());
+
+    let dlat = lat2 - lat1;
+    let dlon = lon2 - lon1;
+    let h = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+
+    2.0 * EARTH_RADIUS_M * h.sqrt().asin()
+}
+
+pub fn polyline_length(points: &[(f64, f64)]) -> f64 {
+    points
+        .windows(2)
+        .map(|w| haversine_distance(w[0], w[1]))
+        .sum()
+}
+
+pub fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub fn write_u32<W: Write>(f: &mut W, x: u32) -> Result<(), io::Error> {
+    f.write_all(&x.to_le_bytes())
+}
+
+pub fn read_array<R: Read, T>(
+    f: &mut R,
+    len: usize,
+    read_value: fn(&mut R) -> Result<T, io::Error>,
+) -> Result<Vec<T>, io::Error> {
+    (0..len).map(|_| read_value(f)).collect()
+}
+
+pub fn read_u8<R: Read>(f: &mut R) -> Result<u8, io::Error> {
+    let mut b = [0u8; 1];
+    f.read_exact(&mut b)?;
+    Ok(b[0])
+}
+
+pub fn read_u32<R: Read>(f: &mut R) -> Result<u32, io::Error> {
+    let mut b = [0u8; 4];
+    f.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+pub fn read_i32<R: Read>(f: &mut R) -> Result<i32, io::Error> {
+    let mut b = [0u8; 4];
+    f.read_exact(&mut b)?;
+    Ok(i32::from_le_bytes(b))
+}
+
+pub fn read_i64<R: Read>(f: &mut R) -> Result<i64, io::Error> {
+    let mut b = [0u8; 8];
+    f.read_exact(&mut b)?;
+    Ok(i64::from_le_bytes(b))
+}
+
+pub fn read_u64<R: Read>(f: &mut R) -> Result<u64, io::Error> {
+    let mut b = [0u8; 8];
+    f.read_exact(&mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
+
+pub fn read_f64<R: Read>(f: &mut R) -> Result<f64, io::Error> {
+    let mut b = [0u8; 8];
+    f.read_exact(&mut b)?;
+    Ok(f64::from_le_bytes(b))
+}
+
+// applies `f` to every item on all available cores, keeping the order of the items
+pub fn parallel_map<T: Sync, R: Send, F: Fn(&T) -> R + Sync>(items: &[T], f: F) -> Vec<R> {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = items.len().div_ceil(threads).max(1);
+    let f = &f;
+
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = items
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(f).collect::<Vec<R>>()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect()
+    })
+}
+
 #[test]
 fn digit_test() {
     let s = "85abc22".to_string();
@@ -17,3 +114,30 @@ fn char_test() {
     println!("{}", result);
     assert!(result == "bdf");
 }
+
+#[test]
+fn haversine_test() {
+    // one degree of latitude is roughly 111.2km
+    let result = haversine_distance((0.0, 0.0), (1.0, 0.0));
+    println!("{}", result);
+    assert!((result - 111_195.0).abs() < 10.0);
+}
+
+#[test]
+fn polyline_length_test() {
+    let points = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0)];
+    let result = polyline_length(&points);
+    let expected =
+        haversine_distance(points[0], points[1]) + haversine_distance(points[1], points[2]);
+    println!("{}", result);
+    assert!((result - expected).abs() < 1e-6);
+    assert!(polyline_length(&points[..1]) == 0.0);
+}
+
+#[test]
+fn parallel_map_test() {
+    let items: Vec<u32> = (0..1000).collect();
+    let result = parallel_map(&items, |x| x * 2);
+    assert!(result == items.iter().map(|x| x * 2).collect::<Vec<u32>>());
+    assert!(parallel_map(&Vec::<u32>::new(), |x| *x).is_empty());
+}